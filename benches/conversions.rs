@@ -0,0 +1,162 @@
+// Copyright 2019 The Exonum Team, 2019 Witnet Foundation
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compares the allocating conversions with the ones reusing the buffers, and the
+//! encoding and decoding through the protobuf messages with the direct wire format.
+//! Run with `cargo bench --bench conversions`.
+
+use crate::proto::ProtobufConvert;
+use protobuf_convert::ProtobufConvert;
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../tests/proto/mod.rs"]
+mod proto;
+
+const ITERATIONS: u32 = 200_000;
+const BATCH_SIZE: u32 = 1_000;
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SimpleMessage", into_pb, reuse_buffers, wire)]
+struct Message {
+    #[protobuf_convert(number = 1)]
+    id: u32,
+    #[protobuf_convert(number = 2)]
+    name: String,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::LogMessage", into_pb, reuse_buffers, wire)]
+struct LogMessage {
+    #[protobuf_convert(number = 1, enumeration)]
+    level: proto::Level,
+    #[protobuf_convert(number = 2)]
+    text: String,
+    #[protobuf_convert(number = 3, proto_type = "message")]
+    source: Message,
+    #[protobuf_convert(number = 4, proto_type = "message")]
+    related: [Message; 2],
+}
+
+fn log_message() -> LogMessage {
+    let message = Message {
+        id: 1,
+        name: "source".repeat(8),
+    };
+    LogMessage {
+        level: proto::Level::LEVEL_ERROR,
+        text: "failure".repeat(32),
+        source: message.clone(),
+        related: [message.clone(), message],
+    }
+}
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    // Warms up the caches and the reused buffers.
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed: Duration = start.elapsed();
+    report(name, elapsed);
+}
+
+/// Measures `routine` on the inputs created by `setup`, like `iter_batched` of criterion.
+/// The inputs are created and the outputs are dropped outside of the measured time.
+fn bench_batched<I, O>(name: &str, mut setup: impl FnMut() -> I, mut routine: impl FnMut(I) -> O) {
+    let mut run = |iterations: u32| {
+        let mut elapsed = Duration::default();
+        for _ in 0..iterations / BATCH_SIZE {
+            let inputs = (0..BATCH_SIZE).map(|_| setup()).collect::<Vec<_>>();
+            let mut outputs = Vec::with_capacity(inputs.len());
+            let start = Instant::now();
+            for input in inputs {
+                outputs.push(routine(black_box(input)));
+            }
+            elapsed += start.elapsed();
+            black_box(outputs);
+        }
+        elapsed
+    };
+
+    // Warms up the caches and the reused buffers.
+    run(ITERATIONS / 10);
+    report(name, run(ITERATIONS));
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{:<28} {:>10.1} ns/iter",
+        name,
+        elapsed.as_nanos() as f64 / f64::from(ITERATIONS)
+    );
+}
+
+fn main() {
+    let log = log_message();
+
+    bench("to_pb", || {
+        black_box(black_box(&log).to_pb());
+    });
+    let mut pb_log = proto::LogMessage::new();
+    bench("to_pb_into", || {
+        black_box(&log).to_pb_into(&mut pb_log);
+        black_box(&pb_log);
+    });
+    bench_batched("into_pb", || log.clone(), LogMessage::into_pb);
+
+    let pb_log = log.to_pb();
+    bench_batched(
+        "from_pb",
+        || pb_log.clone(),
+        |pb_log| LogMessage::from_pb(pb_log).unwrap(),
+    );
+    let mut decoded = log_message();
+    bench_batched(
+        "from_pb_into",
+        || pb_log.clone(),
+        |pb_log| decoded.from_pb_into(pb_log).unwrap(),
+    );
+
+    let mut bytes = Vec::new();
+    bench("to_pb + write_to", || {
+        bytes.clear();
+        let mut os = protobuf::CodedOutputStream::vec(&mut bytes);
+        protobuf::Message::write_to(&black_box(&log).to_pb(), &mut os).unwrap();
+        os.flush().unwrap();
+    });
+    bench("encode", || {
+        bytes.clear();
+        let mut os = protobuf::CodedOutputStream::vec(&mut bytes);
+        black_box(&log).encode(&mut os).unwrap();
+        os.flush().unwrap();
+    });
+
+    bench("parse_from_bytes + from_pb", || {
+        let pb_log: proto::LogMessage =
+            protobuf::Message::parse_from_bytes(black_box(&bytes)).unwrap();
+        black_box(LogMessage::from_pb(pb_log).unwrap());
+    });
+    bench("decode", || {
+        let mut is = protobuf::CodedInputStream::from_bytes(black_box(&bytes));
+        black_box(LogMessage::decode(&mut is).unwrap());
+    });
+}
@@ -19,15 +19,34 @@ use std::{fs, io::prelude::*, path::Path};
 fn main() {
     let out_dir = std::env::var("OUT_DIR").expect("Unable to get OUT_DIR");
 
-    protoc_rust::run(protoc_rust::Args {
-        out_dir: &out_dir,
-        input: &["tests/proto/message.proto"],
-        includes: &["tests/proto"],
-        customize: Customize {
+    protoc_rust::Codegen::new()
+        .out_dir(&out_dir)
+        .input("tests/proto/message.proto")
+        .include("tests/proto")
+        .customize(Customize {
             ..Default::default()
-        },
-    })
-    .expect("Couldn't compile proto sources");
+        })
+        .run()
+        .expect("Couldn't compile proto sources");
+
+    // Messages with the public fields, accessed without the accessor methods.
+    let fields_out_dir = Path::new(&out_dir).join("fields");
+    fs::create_dir_all(&fields_out_dir).expect("Unable to create fields directory");
+    protoc_rust::Codegen::new()
+        .out_dir(&fields_out_dir)
+        .input("tests/proto/fields.proto")
+        .include("tests/proto")
+        .customize(Customize {
+            expose_fields: Some(true),
+            ..Default::default()
+        })
+        .run()
+        .expect("Couldn't compile proto sources with exposed fields");
+    fs::write(
+        fields_out_dir.join("mod.rs"),
+        "pub use self::fields::*; mod fields;",
+    )
+    .expect("Unable to write fields mod.rs file");
 
     let mod_file_content = r#"
         pub use self::message::*; 
@@ -36,6 +55,6 @@ fn main() {
     let mod_file_path = Path::new(&out_dir).join("mod.rs");
 
     let mut file = fs::File::create(&mod_file_path).expect("Unable to create mod.rs file");
-    file.write_all(&mod_file_content.to_string().as_ref())
+    file.write_all(mod_file_content.to_string().as_ref())
         .expect("Unable to write mod.rs file");
 }
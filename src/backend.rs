@@ -0,0 +1,317 @@
+// Copyright 2019 The Exonum Team, 2019 Witnet Foundation
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use darling::FromMeta;
+use proc_macro2::{Ident, Span, TokenStream};
+use quote::quote;
+
+/// Keywords which cannot be used as the names of the generated struct fields.
+const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Keywords which cannot be used as raw identifiers.
+const NON_RAW_KEYWORDS: &[&str] = &["crate", "extern", "self", "Self", "super"];
+
+/// Protobuf implementation which generates the converted structs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// rust-protobuf 2.x, with `get_x()`/`set_x()` accessors.
+    #[default]
+    Protobuf,
+    /// rust-protobuf 3.x, with public fields and `MessageField` for the message fields.
+    Protobuf3,
+    /// prost, with public fields and `Option` for the message fields.
+    Prost,
+    /// quick-protobuf, with public fields, `Option` for the message fields and
+    /// `Cow` for the strings and bytes.
+    QuickProtobuf,
+}
+
+impl FromMeta for Backend {
+    fn from_string(value: &str) -> darling::Result<Self> {
+        match value {
+            "protobuf" => Ok(Backend::Protobuf),
+            "protobuf3" => Ok(Backend::Protobuf3),
+            "prost" => Ok(Backend::Prost),
+            "quick-protobuf" => Ok(Backend::QuickProtobuf),
+            _ => Err(darling::Error::unknown_value(value)),
+        }
+    }
+}
+
+impl Backend {
+    /// Accessors used by default with the backend.
+    pub fn default_accessors(self) -> Accessors {
+        match self {
+            Backend::Protobuf => Accessors::Methods,
+            _ => Accessors::Fields,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Protobuf => "protobuf",
+            Backend::Protobuf3 => "protobuf3",
+            Backend::Prost => "prost",
+            Backend::QuickProtobuf => "quick-protobuf",
+        }
+    }
+
+    /// Returns `true` if the generated structs implement `protobuf::Message`.
+    pub fn implements_message(self) -> bool {
+        matches!(self, Backend::Protobuf | Backend::Protobuf3)
+    }
+
+    /// Returns `true` if the attribute can be used with the code generated by the backend.
+    pub fn supports(self, attribute: &str) -> bool {
+        match attribute {
+            "path" | "unknown_fields" | "any" => self.implements_message(),
+            // quick-protobuf generates the Rust enums for the enum fields.
+            "enumeration" => self != Backend::QuickProtobuf,
+            "wrapper" => self != Backend::QuickProtobuf,
+            // These rely on the reflection, the well-known types and the accessors
+            // of rust-protobuf 2.x.
+            "deny_unknown_fields" | "field_mask" | "merge" | "reuse_buffers" => {
+                self == Backend::Protobuf
+            }
+            // The fields are written with the output stream of rust-protobuf 2.x.
+            "wire" => self == Backend::Protobuf,
+            _ => true,
+        }
+    }
+
+    /// Error for the attribute which cannot be used with the backend.
+    pub fn unsupported(self, attribute: &str) -> darling::Error {
+        darling::Error::custom(format!(
+            "`{}` is not supported by the `{}` backend",
+            attribute,
+            self.name()
+        ))
+    }
+
+    /// Name of the generated struct field for the protobuf field, which is also
+    /// the suffix of its accessor methods.
+    pub fn field_ident(self, name: &Ident) -> Ident {
+        let name = name.to_string();
+        if !RUST_KEYWORDS.contains(&name.as_str()) {
+            return Ident::new(&name, Span::call_site());
+        }
+
+        match self {
+            Backend::Protobuf => Ident::new(&format!("field_{}", name), Span::call_site()),
+            Backend::Prost if !NON_RAW_KEYWORDS.contains(&name.as_str()) => {
+                Ident::new_raw(&name, Span::call_site())
+            }
+            Backend::Protobuf3 | Backend::Prost => {
+                Ident::new(&format!("{}_", name), Span::call_site())
+            }
+            Backend::QuickProtobuf => Ident::new(&format!("{}_pb", name), Span::call_site()),
+        }
+    }
+
+    /// Nested message of the directly accessed message field, which is the default
+    /// instance if the field is absent.
+    pub fn message_ref(self, field: TokenStream) -> TokenStream {
+        match self {
+            Backend::Protobuf => quote! {
+                #field.as_ref().unwrap_or_else(|| protobuf::Message::default_instance())
+            },
+            _ => field,
+        }
+    }
+
+    /// Mutable nested message of the directly accessed message field, which is inserted
+    /// if the field is absent.
+    pub fn message_mut(self, field: TokenStream) -> TokenStream {
+        quote! { #field.mut_or_insert_default() }
+    }
+
+    /// Helper traits mapping the types of the directly accessed protobuf fields to the
+    /// protobuf types of the struct fields, e.g. `Option<T>` of the message fields to `T`.
+    pub fn field_helpers(self) -> TokenStream {
+        let message_fields = match self {
+            Backend::Protobuf => quote! {
+                impl<T: Default> FromPbField<T> for protobuf::SingularPtrField<T> {
+                    fn from_pb_field(self) -> T {
+                        self.into_option().unwrap_or_default()
+                    }
+                }
+
+                impl<T: Default> FromPbField<T> for protobuf::SingularField<T> {
+                    fn from_pb_field(self) -> T {
+                        self.into_option().unwrap_or_default()
+                    }
+                }
+
+                impl<T> FromPbField<Vec<T>> for protobuf::RepeatedField<T> {
+                    fn from_pb_field(self) -> Vec<T> {
+                        self.into_vec()
+                    }
+                }
+
+                impl<T> IntoPbField<protobuf::SingularPtrField<T>> for T {
+                    fn into_pb_field(self) -> protobuf::SingularPtrField<T> {
+                        protobuf::SingularPtrField::some(self)
+                    }
+                }
+
+                impl<T> IntoPbField<protobuf::SingularField<T>> for T {
+                    fn into_pb_field(self) -> protobuf::SingularField<T> {
+                        protobuf::SingularField::some(self)
+                    }
+                }
+
+                impl<T> IntoPbField<protobuf::RepeatedField<T>> for Vec<T> {
+                    fn into_pb_field(self) -> protobuf::RepeatedField<T> {
+                        protobuf::RepeatedField::from_vec(self)
+                    }
+                }
+
+                // Mirrors `MessageField::mut_or_insert_default` of rust-protobuf 3.x,
+                // `set_default` clears the present messages.
+                trait MutOrInsertDefault<T> {
+                    fn mut_or_insert_default(&mut self) -> &mut T;
+                }
+
+                impl<T: Default + protobuf::Clear> MutOrInsertDefault<T> for protobuf::SingularPtrField<T> {
+                    fn mut_or_insert_default(&mut self) -> &mut T {
+                        if self.is_none() {
+                            self.set_default();
+                        }
+                        self.as_mut().unwrap()
+                    }
+                }
+            },
+            Backend::Protobuf3 => quote! {
+                impl<T: Default> FromPbField<T> for protobuf::MessageField<T> {
+                    fn from_pb_field(self) -> T {
+                        self.into_option().unwrap_or_default()
+                    }
+                }
+
+                impl<T> IntoPbField<protobuf::MessageField<T>> for T {
+                    fn into_pb_field(self) -> protobuf::MessageField<T> {
+                        protobuf::MessageField::some(self)
+                    }
+                }
+            },
+            Backend::QuickProtobuf => quote! {
+                impl FromPbField<String> for std::borrow::Cow<'_, str> {
+                    fn from_pb_field(self) -> String {
+                        self.into_owned()
+                    }
+                }
+
+                impl FromPbField<Vec<u8>> for std::borrow::Cow<'_, [u8]> {
+                    fn from_pb_field(self) -> Vec<u8> {
+                        self.into_owned()
+                    }
+                }
+
+                impl<'a> IntoPbField<std::borrow::Cow<'a, str>> for String {
+                    fn into_pb_field(self) -> std::borrow::Cow<'a, str> {
+                        std::borrow::Cow::Owned(self)
+                    }
+                }
+
+                impl<'a> IntoPbField<std::borrow::Cow<'a, [u8]>> for Vec<u8> {
+                    fn into_pb_field(self) -> std::borrow::Cow<'a, [u8]> {
+                        std::borrow::Cow::Owned(self)
+                    }
+                }
+            },
+            Backend::Prost => quote! {},
+        };
+
+        quote! {
+            trait FromPbField<T> {
+                fn from_pb_field(self) -> T;
+            }
+
+            impl<T> FromPbField<T> for T {
+                fn from_pb_field(self) -> T {
+                    self
+                }
+            }
+
+            impl<T: Default> FromPbField<T> for Option<T> {
+                fn from_pb_field(self) -> T {
+                    self.unwrap_or_default()
+                }
+            }
+
+            trait IntoPbField<T> {
+                fn into_pb_field(self) -> T;
+            }
+
+            impl<T> IntoPbField<T> for T {
+                fn into_pb_field(self) -> T {
+                    self
+                }
+            }
+
+            impl<T> IntoPbField<Option<T>> for T {
+                fn into_pb_field(self) -> Option<T> {
+                    Some(self)
+                }
+            }
+
+            #message_fields
+        }
+    }
+}
+
+/// Style of access to the fields of the generated structs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accessors {
+    /// `get_x()`, `set_x()` and other accessor methods.
+    Methods,
+    /// Public fields of the generated structs.
+    Fields,
+}
+
+impl FromMeta for Accessors {
+    fn from_string(value: &str) -> darling::Result<Self> {
+        match value {
+            "methods" => Ok(Accessors::Methods),
+            "fields" => Ok(Accessors::Fields),
+            _ => Err(darling::Error::unknown_value(value)),
+        }
+    }
+}
+
+impl Accessors {
+    /// Converts the value of the protobuf field into the protobuf type of the struct field.
+    pub fn read_pb_field(self, value: TokenStream) -> TokenStream {
+        match self {
+            Accessors::Methods => value,
+            Accessors::Fields => quote! { FromPbField::from_pb_field(#value) },
+        }
+    }
+
+    /// Converts the protobuf type of the struct field into the value of the protobuf field,
+    /// `convert_into` specifies whether the setter needs `Into` conversion.
+    pub fn write_pb_field(self, value: TokenStream, convert_into: bool) -> TokenStream {
+        match self {
+            Accessors::Methods if convert_into => quote! { #value.into() },
+            Accessors::Methods => value,
+            Accessors::Fields => quote! { IntoPbField::into_pb_field(#value) },
+        }
+    }
+}
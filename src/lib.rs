@@ -16,7 +16,10 @@
 
 extern crate proc_macro;
 
+mod backend;
 mod pb_convert;
+mod well_known;
+mod wire;
 
 use proc_macro::TokenStream;
 use syn::{Attribute, NestedMeta};
@@ -92,6 +95,144 @@ const DEFAULT_ONEOF_FIELD_NAME: &str = "kind";
 ///
 /// Path is the name of the corresponding protobuf generated struct.
 ///
+/// * `#[protobuf_convert(source = "path", field_mask)]`
+///
+/// Implements `to_pb_masked(&self, mask: &FieldMask)`, which must be declared in the
+/// `ProtobufConvert` trait, setting only the fields selected by the mask paths.
+/// Nested paths are passed to `to_pb_masked` of the field type, and paths which do not
+/// exist on the type result in an `UnknownMaskPath` error, which must be in scope,
+/// naming the full path.
+///
+/// * `#[protobuf_convert(source = "path", merge)]`
+///
+/// Implements `merge_from_pb(&mut self, pb, mask: Option<&FieldMask>)`, which must be
+/// declared in the `ProtobufConvert` trait. It overwrites only the fields set in `pb`,
+/// or selected by the mask if it is not empty, and merges the nested derived fields
+/// recursively.
+///
+/// * `#[protobuf_convert(source = "path", into_pb)]`
+///
+/// Implements the consuming `into_pb(self)`, which must be declared in the
+/// `ProtobufConvert` trait. The fields are moved into the protobuf message and converted
+/// with `into_pb` of their types, except the shared pointers and the fields converted
+/// by reference, e.g. with `with` or `display_fromstr`. Also supported by the enums.
+///
+/// * `#[protobuf_convert(source = "path", reuse_buffers)]`
+///
+/// Implements `to_pb_into(&self, &mut ProtoStruct)` and `from_pb_into(&mut self, pb)`,
+/// which must be declared in the `ProtobufConvert` trait. `to_pb_into` clears the
+/// existing message and writes the strings, bytes, messages and repeated fields through
+/// `mut_x()`, keeping their allocations, while `from_pb_into` decodes the nested derived
+/// fields and the array elements in place. Only supported by rust-protobuf 2.x with the
+/// accessor methods.
+///
+/// * `#[protobuf_convert(source = "path", wire)]`
+///
+/// Implements `encode(&self, &mut CodedOutputStream)`, `encoded_len(&self)` and
+/// `decode(&mut CodedInputStream)`, which must be declared in the `ProtobufConvert` trait,
+/// writing and reading the protobuf wire format directly from and into the struct. The
+/// lengths of the nested messages are computed once before writing them. `decode` applies
+/// the same checks as `from_pb`. Each field is marked with `number = N`, and its protobuf
+/// type is inferred from the scalar, string and bytes Rust types or specified with
+/// `proto_type = "sint64"`, which is required for messages. Repeated fields declared as
+/// `[packed = true]` are marked with `packed`. The fields are checked against the message
+/// descriptor in debug builds, which must declare them in the order of their numbers.
+/// Only supported by rust-protobuf 2.x.
+///
+/// * `#[protobuf_convert(unknown_fields)]`
+///
+/// Captures the unknown fields of the protobuf message in a field of type
+/// `protobuf::UnknownFields` on `from_pb` and writes them back on `to_pb`.
+///
+/// * `#[protobuf_convert(source = "path", deny_unknown_fields)]`
+///
+/// Makes `from_pb` and `merge_from_pb` return an error with the unknown field tags
+/// if the message or any of its nested messages contains unknown fields.
+///
+/// * `#[protobuf_convert(source = "path", register)]`
+///
+/// Defines the `proto_full_name()` function of the struct, which returns the full name
+/// from the message descriptor unless specified with `full_name = "package.Message"`.
+/// The name is required for the backends without descriptors. Enums marked with
+/// `any` are converted into `google.protobuf.Any`, whose type URL selects the variant
+/// by the full name of its registered type:
+///
+/// ```ignore
+/// #[derive(Debug, Clone, ProtobufConvert)]
+/// #[protobuf_convert(any)]
+/// pub enum Payload {
+///     Transfer(Transfer),
+///     Stake(Stake),
+/// }
+/// ```
+///
+/// * `#[protobuf_convert(path = "meta.created.seconds")]`
+///
+/// Maps the struct field to a field of the nested protobuf message. Intermediate
+/// messages are created on `to_pb`. On `from_pb`, if some of them are absent, `Option`
+/// fields become `None`, fields marked as `required` return an error and other fields
+/// are read from the default messages.
+///
+/// * `#[protobuf_convert(source = "path", backend = "prost")]`
+///
+/// Generates the code for the structs and oneof enums generated by prost instead of
+/// rust-protobuf. The fields are accessed directly, message fields are unwrapped from
+/// `Option` and oneofs are read from the `message::Kind` enums. The `i32` fields of the
+/// protobuf enums are marked with `enumeration` and converted using `TryFrom<i32>` and
+/// `From<_> for i32` of the `ProtoStruct` of the field type. `path`, `unknown_fields`,
+/// `any` and the attributes limited to rust-protobuf 2.x listed below are not supported.
+///
+/// * `#[protobuf_convert(source = "path", backend = "protobuf3")]`
+///
+/// Generates the code for rust-protobuf 3.x, which also uses public fields and
+/// `message::Kind` oneof enums. Message fields are unwrapped from `MessageField`, and
+/// `enumeration` fields are converted from `EnumOrUnknown` of the `ProtoStruct` of the
+/// field type. The attributes limited to rust-protobuf 2.x listed below are not supported.
+///
+/// * `#[protobuf_convert(source = "path<'a>", backend = "quick-protobuf")]`
+///
+/// Generates the code for the structs generated by pb-rs for quick-protobuf, which may
+/// borrow the decoded bytes. `source` may include the lifetime of the message, which
+/// is `'static` for the owned Rust types and the lifetime of the struct for the types
+/// borrowing from the message, e.g. with `Cow<'a, str>` fields. `String` and `Vec<u8>`
+/// fields are converted from `Cow` as well. Oneofs use the `mod_Message::OneOfkind`
+/// enums, and the attributes unsupported by prost or `wrapper` cannot be used.
+///
+/// `deny_unknown_fields`, `field_mask`, `merge`, `reuse_buffers` and `wire` rely on the
+/// reflection, the accessors and the output stream of rust-protobuf 2.x, so they can
+/// only be used with the default `protobuf` backend.
+///
+/// * `#[protobuf_convert(source = "path", accessors = "fields")]`
+///
+/// Accesses the public fields of the protobuf structs, e.g. generated by rust-protobuf
+/// 2.x with `expose_fields`, instead of the `get_x()`/`set_x()` methods. `"methods"`
+/// is the default for rust-protobuf 2.x and is not supported by the other backends.
+/// `field_mask` and `merge` cannot be used with the fields.
+///
+/// * `#[protobuf_convert(enumeration)]`
+///
+/// Marks the protobuf enum fields, which are converted through `EnumOrUnknown` with
+/// rust-protobuf 3.x and through their integer values with prost, and are encoded
+/// as enums by `wire`.
+///
+/// * `#[protobuf_convert(proto_type = "uint32")]`
+///
+/// Specifies the protobuf type of the field. With rust-protobuf 2.x, the fields of types
+/// other than the primitive ones are moved with `take_x()` and reused through `mut_x()`,
+/// so the types converted into scalars, e.g. newtypes over `u32`, must be marked with
+/// their scalar type to be read with `get_x()`.
+///
+/// * `#[protobuf_convert(plain)]`
+///
+/// Converts the field with the `ProtobufConvert` implementation of its type instead of
+/// the built-in handling of `Box`, `Rc`, `Arc`, `Cow`, arrays and sets.
+///
+/// * `#[protobuf_convert(getter = "get_field_type", setter = "set_field_type")]`
+///
+/// Overrides the accessor methods of the protobuf field, or the name of the struct
+/// field with `accessors = "fields"`. Fields named after Rust keywords, such as
+/// `r#type`, are mapped to the names generated by the backend without overrides.
+///
 /// * `#[protobuf_convert(source = "path", serde_pb_convert)]`
 ///
 /// Implement `serde::{Serialize, Deserialize}` using structs that were generated with
@@ -118,6 +259,38 @@ pub fn generate_protobuf_convert(input: TokenStream) -> TokenStream {
     pb_convert::implement_protobuf_convert(input)
 }
 
+/// Implements `ProtobufConvert` for the Rust counterparts of the well-known protobuf types.
+///
+/// The trait is defined by the user, so the macro must be invoked in the module where
+/// `ProtobufConvert` is in scope:
+///
+/// ```ignore
+/// pub trait ProtobufConvert: Sized {
+///     // ...
+/// }
+///
+/// protobuf_convert::impl_well_known_types!(chrono, time, serde_json);
+/// ```
+///
+/// The following conversions are implemented:
+///
+/// * `std::time::SystemTime` and `std::time::Duration` to `google.protobuf.Timestamp`
+///   and `google.protobuf.Duration`.
+/// * `chrono::DateTime<Utc>` and `chrono::Duration`, if `chrono` is passed to the macro.
+/// * `time::OffsetDateTime` and `time::Duration`, if `time` is passed to the macro.
+/// * `serde_json::Value` and `serde_json::Map<String, Value>` to `google.protobuf.Value`
+///   and `google.protobuf.Struct`, if `serde_json` is passed to the macro. JSON arrays
+///   are mapped to `google.protobuf.ListValue` within `Value`.
+///
+/// `to_pb` clamps the timestamps and durations into the ranges allowed by the protobuf
+/// specification. `from_pb` returns an error if the value is out of these ranges or cannot
+/// be represented by the Rust type, e.g. a negative duration converted into
+/// `std::time::Duration` or a non-finite number converted into JSON.
+#[proc_macro]
+pub fn impl_well_known_types(input: TokenStream) -> TokenStream {
+    well_known::implement_well_known_types(input)
+}
+
 pub(crate) fn find_protobuf_convert_meta(args: &[Attribute]) -> Option<NestedMeta> {
     args.as_ref()
         .iter()
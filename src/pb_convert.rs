@@ -13,29 +13,46 @@
 // limitations under the License.
 
 use darling::{FromDeriveInput, FromMeta};
-use heck::SnakeCase;
+use heck::{CamelCase, SnakeCase};
 use proc_macro::TokenStream;
-use proc_macro2::{Ident, Span};
+use proc_macro2::{Ident, Span, TokenTree};
 use quote::{quote, ToTokens};
-use syn::{Attribute, Data, DataEnum, DataStruct, DeriveInput, Fields, Path, Type, Variant};
+use syn::{
+    ext::IdentExt, parse::Parser, Attribute, Data, DataEnum, DataStruct, DeriveInput, Expr, Field,
+    Fields, GenericArgument, Generics, Path, PathArguments, Type, Variant,
+};
 
 use std::convert::TryFrom;
 
-use super::{find_protobuf_convert_meta, DEFAULT_ONEOF_FIELD_NAME, PB_SNAKE_CASE_ATTRIBUTE};
+use super::{
+    backend::{Accessors, Backend},
+    find_protobuf_convert_meta,
+    wire::{ProtoType, MAX_FIELD_NUMBER},
+    DEFAULT_ONEOF_FIELD_NAME, PB_SNAKE_CASE_ATTRIBUTE,
+};
 
-#[derive(Debug, FromMeta)]
+#[derive(Debug, Default, FromMeta)]
 #[darling(default)]
 struct ProtobufConvertStructAttrs {
     source: Option<Path>,
+    backend: Backend,
+    accessors: Option<Accessors>,
     serde_pb_convert: bool,
+    register: bool,
+    full_name: Option<String>,
+    field_mask: bool,
+    merge: bool,
+    deny_unknown_fields: bool,
+    into_pb: bool,
+    reuse_buffers: bool,
+    wire: bool,
 }
 
-impl Default for ProtobufConvertStructAttrs {
-    fn default() -> Self {
-        Self {
-            source: None,
-            serde_pb_convert: false,
-        }
+impl ProtobufConvertStructAttrs {
+    /// Style of access to the protobuf fields, which depends on the backend by default.
+    fn accessors(&self) -> Accessors {
+        self.accessors
+            .unwrap_or_else(|| self.backend.default_accessors())
     }
 }
 
@@ -53,20 +70,26 @@ impl TryFrom<&[Attribute]> for ProtobufConvertStructAttrs {
 #[darling(default)]
 struct ProtobufConvertEnumAttrs {
     source: Option<Path>,
+    backend: Backend,
     serde_pb_convert: bool,
     impl_from_trait: bool,
     rename: Rename,
     oneof_field: Ident,
+    any: bool,
+    into_pb: bool,
 }
 
 impl Default for ProtobufConvertEnumAttrs {
     fn default() -> Self {
         Self {
             source: None,
+            backend: Backend::default(),
             oneof_field: syn::parse_str(DEFAULT_ONEOF_FIELD_NAME).unwrap(),
             serde_pb_convert: false,
             impl_from_trait: false,
             rename: Default::default(),
+            any: false,
+            into_pb: false,
         }
     }
 }
@@ -84,7 +107,8 @@ impl TryFrom<&[Attribute]> for ProtobufConvertEnumAttrs {
 #[derive(Debug)]
 struct ProtobufConvertStruct {
     name: Ident,
-    fields: Vec<(Ident, ProtobufConvertFieldAttrs)>,
+    generics: Generics,
+    fields: Vec<ParsedField>,
     attrs: ProtobufConvertStructAttrs,
 }
 
@@ -92,7 +116,26 @@ struct ProtobufConvertStruct {
 #[darling(default)]
 struct ProtobufConvertFieldAttrs {
     skip: bool,
+    to_pb_only: bool,
+    from_pb_only: bool,
+    compute: Option<String>,
     with: Option<Path>,
+    path: Option<String>,
+    required: bool,
+    default_as_none: bool,
+    default_value: Option<String>,
+    deny_duplicates: bool,
+    plain: bool,
+    via: Option<String>,
+    display_fromstr: bool,
+    wrapper: bool,
+    unknown_fields: bool,
+    enumeration: bool,
+    getter: Option<String>,
+    setter: Option<String>,
+    number: Option<u32>,
+    proto_type: Option<ProtoType>,
+    packed: bool,
 }
 
 impl TryFrom<&[Attribute]> for ProtobufConvertFieldAttrs {
@@ -105,107 +148,2877 @@ impl TryFrom<&[Attribute]> for ProtobufConvertFieldAttrs {
     }
 }
 
-fn get_field_names(
+#[derive(Debug)]
+struct ParsedField {
+    name: Ident,
+    ty: Type,
+    /// Path to the corresponding protobuf field, the last segment is the field itself
+    /// and the others are the intermediate messages.
+    pb_path: Vec<Ident>,
+    /// Function computing the field value from `&Self` for `to_pb_only` fields.
+    compute: Option<Expr>,
+    /// Value used instead of the default protobuf value.
+    default_value: Option<Expr>,
+    /// Wire type the value is converted through.
+    via: Option<Type>,
+    /// Accessor overriding `get_x()` or the field read by `from_pb`.
+    getter: Option<Ident>,
+    /// Accessor overriding `set_x()` or the field written by `to_pb`.
+    setter: Option<Ident>,
+    /// Protobuf implementation of the struct.
+    backend: Backend,
+    /// Style of access to the protobuf fields.
+    accessors: Accessors,
+    /// Whether the protobuf message field also contains the paths of other fields.
+    shared: bool,
+    attrs: ProtobufConvertFieldAttrs,
+}
+
+impl TryFrom<&Field> for ParsedField {
+    type Error = darling::Error;
+
+    fn try_from(value: &Field) -> Result<Self, Self::Error> {
+        let attrs = ProtobufConvertFieldAttrs::try_from(value.attrs.as_ref())?;
+        let name = value.ident.clone().ok_or_else(|| {
+            darling::Error::unsupported_shape("Struct fields must have an identifier.")
+        })?;
+
+        // Protobuf names may be keywords, which are mapped to the generated names
+        // by the backend.
+        let pb_path = match &attrs.path {
+            Some(path) => path
+                .split('.')
+                .map(|segment| {
+                    Ident::parse_any
+                        .parse_str(segment)
+                        .map(|segment| segment.unraw())
+                        .map_err(|_| {
+                            darling::Error::custom(format!(
+                                "Invalid protobuf field path `{}`",
+                                path
+                            ))
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => vec![name.unraw()],
+        };
+        let parse_accessor = |accessor: &Option<String>| match accessor {
+            Some(accessor) => syn::parse_str::<Ident>(accessor)
+                .map(Some)
+                .map_err(|_| darling::Error::custom(format!("Invalid accessor `{}`", accessor))),
+            None => Ok(None),
+        };
+        let getter = parse_accessor(&attrs.getter)?;
+        let setter = parse_accessor(&attrs.setter)?;
+
+        let modes = [attrs.skip, attrs.to_pb_only, attrs.from_pb_only];
+        if modes.iter().filter(|&&mode| mode).count() > 1 {
+            return Err(darling::Error::custom(
+                "Only one of `skip`, `to_pb_only` and `from_pb_only` can be specified",
+            ));
+        }
+        let compute = match &attrs.compute {
+            Some(_) if !attrs.to_pb_only => {
+                return Err(darling::Error::custom(
+                    "`compute` can only be used together with `to_pb_only`",
+                ));
+            }
+            Some(compute) => Some(syn::parse_str::<Expr>(compute).map_err(|_| {
+                darling::Error::custom(format!("Invalid `compute` expression `{}`", compute))
+            })?),
+            None => None,
+        };
+        let default_value = match &attrs.default_value {
+            Some(default_value) => Some(syn::parse_str::<Expr>(default_value).map_err(|_| {
+                darling::Error::custom(format!(
+                    "Invalid `default_value` expression `{}`",
+                    default_value
+                ))
+            })?),
+            None => None,
+        };
+
+        let via = match &attrs.via {
+            Some(via) => Some(
+                syn::parse_str::<Type>(via)
+                    .map_err(|_| darling::Error::custom(format!("Invalid `via` type `{}`", via)))?,
+            ),
+            None => None,
+        };
+
+        let conversions = [
+            attrs.with.is_some(),
+            via.is_some(),
+            attrs.display_fromstr,
+            attrs.enumeration,
+            attrs.plain,
+        ];
+        if conversions.iter().filter(|&&conversion| conversion).count() > 1 {
+            return Err(darling::Error::custom(
+                "Only one of `with`, `via`, `display_fromstr`, `enumeration` and `plain` \
+                 can be specified",
+            ));
+        }
+
+        if attrs.default_as_none && option_inner_type(&value.ty).is_none() {
+            return Err(darling::Error::custom(
+                "`default_as_none` can only be used with `Option` fields",
+            ));
+        }
+        if attrs.default_as_none && default_value.is_some() {
+            return Err(darling::Error::custom(
+                "`default_as_none` and `default_value` cannot be used together",
+            ));
+        }
+        if default_value.is_some() && attrs.path.is_some() && option_inner_type(&value.ty).is_some()
+        {
+            return Err(darling::Error::custom(
+                "`default_value` cannot be used with `Option` fields mapped with `path`",
+            ));
+        }
+
+        if attrs.wrapper && option_inner_type(&value.ty).is_none() {
+            return Err(darling::Error::custom(
+                "`wrapper` can only be used with `Option` fields",
+            ));
+        }
+        if attrs.wrapper && (attrs.default_as_none || default_value.is_some()) {
+            return Err(darling::Error::custom(
+                "`wrapper` cannot be used together with `default_as_none` or `default_value`",
+            ));
+        }
+
+        if attrs.deny_duplicates
+            && (attrs.plain || !is_set_type(option_inner_type(&value.ty).unwrap_or(&value.ty)))
+        {
+            return Err(darling::Error::custom(
+                "`deny_duplicates` can only be used with `HashSet` and `BTreeSet` fields",
+            ));
+        }
+
+        if attrs.unknown_fields
+            && (attrs.skip
+                || attrs.to_pb_only
+                || attrs.from_pb_only
+                || attrs.with.is_some()
+                || attrs.path.is_some()
+                || attrs.required
+                || attrs.default_as_none
+                || attrs.default_value.is_some()
+                || attrs.deny_duplicates
+                || attrs.plain
+                || attrs.via.is_some()
+                || attrs.display_fromstr
+                || attrs.wrapper
+                || attrs.getter.is_some()
+                || attrs.setter.is_some())
+        {
+            return Err(darling::Error::custom(
+                "`unknown_fields` cannot be used together with other attributes",
+            ));
+        }
+
+        if attrs.wrapper && (attrs.getter.is_some() || attrs.setter.is_some()) {
+            return Err(darling::Error::custom(
+                "`getter` and `setter` cannot be used with `wrapper` fields",
+            ));
+        }
+
+        if attrs.required && attrs.path.is_none() {
+            return Err(darling::Error::custom(
+                "`required` can only be used together with `path`",
+            ));
+        }
+        if attrs.required && option_inner_type(&value.ty).is_some() {
+            return Err(darling::Error::custom(
+                "`required` cannot be used with `Option` fields",
+            ));
+        }
+
+        Ok(Self {
+            name,
+            ty: value.ty.clone(),
+            pb_path,
+            compute,
+            default_value,
+            via,
+            getter,
+            setter,
+            backend: Backend::default(),
+            accessors: Accessors::Methods,
+            shared: false,
+            attrs,
+        })
+    }
+}
+
+fn get_fields(
     data: &DataStruct,
-) -> Result<Vec<(Ident, ProtobufConvertFieldAttrs)>, darling::Error> {
-    data.fields
+    backend: Backend,
+    accessors: Accessors,
+) -> Result<Vec<ParsedField>, darling::Error> {
+    let mut fields = data
+        .fields
         .iter()
-        .map(|f| {
-            let attrs = ProtobufConvertFieldAttrs::try_from(f.attrs.as_ref())?;
-            let ident = f.ident.clone().ok_or_else(|| {
-                darling::Error::unsupported_shape("Struct fields must have an identifier.")
-            })?;
-
-            Ok((ident, attrs))
+        .map(|field| {
+            let mut field = ParsedField::try_from(field)?;
+            field.backend = backend;
+            field.accessors = accessors;
+            field.check_backend()?;
+            Ok(field)
         })
-        .collect()
+        .collect::<Result<Vec<_>, darling::Error>>()?;
+
+    let parents = fields
+        .iter()
+        .filter(|field| field.pb_path.len() > 1 && !field.attrs.skip && !field.attrs.to_pb_only)
+        .map(|field| field.pb_path[0].clone())
+        .collect::<Vec<_>>();
+    for field in &mut fields {
+        field.shared = field.pb_path.len() == 1 && parents.contains(&field.pb_path[0]);
+    }
+    Ok(fields)
+}
+
+/// Returns `true` if the path may refer to a standard type, i.e. it is a single imported
+/// name or it starts with `std`, `alloc` or `core`.
+fn is_std_path(path: &Path) -> bool {
+    const STD_CRATES: &[&str] = &["std", "alloc", "core"];
+    path.segments.len() == 1
+        || STD_CRATES
+            .iter()
+            .any(|krate| path.segments[0].ident == krate)
+}
+
+/// Returns the name of the type and its type arguments if it may be a standard type,
+/// so that the types of other crates named e.g. `HashSet` are not mistaken for it.
+fn type_arguments(ty: &Type) -> Option<(&Ident, Vec<&Type>)> {
+    let segment = match ty {
+        Type::Path(type_path) if type_path.qself.is_none() && is_std_path(&type_path.path) => {
+            type_path.path.segments.last()?
+        }
+        _ => return None,
+    };
+
+    let args = match &segment.arguments {
+        PathArguments::None => Vec::new(),
+        PathArguments::AngleBracketed(args) => args
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+        PathArguments::Parenthesized(_) => return None,
+    };
+    Some((&segment.ident, args))
+}
+
+/// Returns `T` if the type is `Option<T>`.
+fn option_inner_type(ty: &Type) -> Option<&Type> {
+    match type_arguments(ty)? {
+        (name, args) if name == "Option" && args.len() == 1 => Some(args[0]),
+        _ => None,
+    }
+}
+
+/// Returns the expression constructing the pointer from its owned value if the type is
+/// `Box<T>`, `Rc<T>`, `Arc<T>` or `Cow<'_, T>`.
+fn pointer_constructor(ty: &Type) -> Option<proc_macro2::TokenStream> {
+    let (name, args) = type_arguments(ty)?;
+    if args.len() != 1 {
+        return None;
+    }
+
+    let mut path = match ty {
+        Type::Path(type_path) => type_path.path.clone(),
+        _ => unreachable!(),
+    };
+    path.segments.last_mut().unwrap().arguments = PathArguments::None;
+    match name.to_string().as_ref() {
+        "Box" | "Rc" | "Arc" => Some(quote! { #path::new }),
+        // `Cow<str>` and `Cow<[u8]>` own different types, so they are converted as is.
+        "Cow" if !is_unsized_type(args[0]) => Some(quote! { #path::Owned }),
+        _ => None,
+    }
+}
+
+/// Returns `true` if the type is `str` or a slice.
+fn is_unsized_type(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path.path.is_ident("str"),
+        Type::Slice(_) => true,
+        _ => false,
+    }
+}
+
+/// Returns `true` if the type is a primitive type, which is read from the scalar
+/// protobuf fields.
+fn is_scalar_type(ty: &Type) -> bool {
+    const SCALAR_TYPES: &[&str] = &[
+        "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16",
+        "u32", "u64", "u128", "usize",
+    ];
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .get_ident()
+            .is_some_and(|ident| SCALAR_TYPES.iter().any(|scalar| ident == scalar)),
+        _ => false,
+    }
+}
+
+/// Returns `true` if the type is `Vec<T>` other than `Vec<u8>`, which corresponds
+/// to the repeated protobuf field.
+fn is_repeated_type(ty: &Type) -> bool {
+    match type_arguments(ty) {
+        Some((name, args)) => {
+            name == "Vec" && !matches!(args[..], [Type::Path(elem)] if elem.path.is_ident("u8"))
+        }
+        None => false,
+    }
+}
+
+/// Returns `true` if the type is `usize` or `isize` converted through a 64-bit or wider
+/// integer of the same signedness, which has no `From` implementation.
+fn is_pointer_sized_widening(ty: &Type, via: &Type) -> bool {
+    let is = |ty: &Type, names: &[&str]| match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .get_ident()
+            .is_some_and(|ident| names.iter().any(|name| ident == name)),
+        _ => false,
+    };
+    (is(ty, &["usize"]) && is(via, &["u64", "u128"]))
+        || (is(ty, &["isize"]) && is(via, &["i64", "i128"]))
+}
+
+/// Infers the type of the protobuf field from the Rust type of its value. Returns `None`
+/// for the types which are not mapped to a protobuf type, such as the other integers,
+/// the newtypes and the messages.
+fn infer_proto_type(ty: &Type) -> Option<ProtoType> {
+    let is_u8 = |ty: &Type| matches!(ty, Type::Path(elem) if elem.path.is_ident("u8"));
+    if let Type::Array(array) = ty {
+        if is_u8(&array.elem) {
+            return Some(ProtoType::Bytes);
+        }
+    }
+
+    let proto_type = match type_arguments(ty)? {
+        (name, args) if args.is_empty() => match name.to_string().as_ref() {
+            "bool" => ProtoType::Bool,
+            "f32" => ProtoType::Float,
+            "f64" => ProtoType::Double,
+            "i32" => ProtoType::Int32,
+            "i64" => ProtoType::Int64,
+            "u32" => ProtoType::Uint32,
+            "u64" => ProtoType::Uint64,
+            "String" => ProtoType::String,
+            _ => return None,
+        },
+        (name, args) if name == "Vec" && is_u8(args[0]) => ProtoType::Bytes,
+        (name, args) if name == "Cow" => match args.first() {
+            Some(Type::Path(pointee)) if pointee.path.is_ident("str") => ProtoType::String,
+            Some(Type::Slice(slice)) if is_u8(&slice.elem) => ProtoType::Bytes,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(proto_type)
+}
+
+/// Conversion of the field value performed by the generated code itself.
+enum ValueConversion<'a> {
+    /// Conversion using the `ProtobufConvert` implementation of the type.
+    Plain,
+    /// Pointer to the value, which is converted using the `ProtobufConvert`
+    /// implementation of the pointee type.
+    Pointer(proc_macro2::TokenStream),
+    /// Byte array mapped to the `bytes` field.
+    Bytes(&'a Expr),
+    /// Array mapped to the repeated field.
+    Array(&'a Expr),
+    /// Set mapped to the repeated field.
+    Set(&'a Type),
+    /// Value converted through the wire type with `TryFrom`.
+    Via(&'a Type),
+    /// Value mapped to the `string` field using `Display` and `FromStr`.
+    DisplayFromStr(&'a Type),
+    /// Protobuf enum represented by its integer value in the protobuf field.
+    Enumeration,
+}
+
+impl<'a> ValueConversion<'a> {
+    fn new(ty: &'a Type) -> Self {
+        if let Type::Array(array) = ty {
+            return match &*array.elem {
+                Type::Path(elem) if elem.path.is_ident("u8") => Self::Bytes(&array.len),
+                _ => Self::Array(&array.len),
+            };
+        }
+
+        if let Some(constructor) = pointer_constructor(ty) {
+            return Self::Pointer(constructor);
+        }
+        if is_set_type(ty) {
+            return Self::Set(ty);
+        }
+        Self::Plain
+    }
+}
+
+/// Returns `true` if the type is `HashSet` or `BTreeSet`.
+fn is_set_type(ty: &Type) -> bool {
+    match type_arguments(ty) {
+        Some((name, args)) => (name == "HashSet" || name == "BTreeSet") && !args.is_empty(),
+        None => false,
+    }
+}
+
+/// Returns `true` if the tokens contain the identifier, e.g. a type parameter.
+fn mentions_ident(tokens: proc_macro2::TokenStream, ident: &Ident) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(token) => token == *ident,
+        TokenTree::Group(group) => mentions_ident(group.stream(), ident),
+        _ => false,
+    })
+}
+
+/// Adds the `ProtobufConvert` bound to the type parameters used by the converted types.
+/// Parameters used only by the skipped fields or converted otherwise are left unbounded.
+fn add_trait_bounds(mut generics: Generics, types: &[proc_macro2::TokenStream]) -> Generics {
+    let params = generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .filter(|param| types.iter().any(|ty| mentions_ident(ty.clone(), param)))
+        .collect::<Vec<_>>();
+    if !params.is_empty() {
+        let where_clause = generics.make_where_clause();
+        for param in params {
+            where_clause
+                .predicates
+                .push(syn::parse_quote!(#param: ProtobufConvert));
+        }
+    }
+    generics
+}
+
+fn join_path(segments: &[Ident]) -> String {
+    segments
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
 }
 
 impl ProtobufConvertStruct {
     fn from_derive_input(
         name: Ident,
+        generics: Generics,
         data: &DataStruct,
         attrs: &[Attribute],
     ) -> Result<Self, darling::Error> {
         let attrs = ProtobufConvertStructAttrs::try_from(attrs)?;
-        let fields = get_field_names(data)?;
+        let accessors = attrs.accessors();
+        if accessors == Accessors::Methods && attrs.backend != Backend::Protobuf {
+            return Err(attrs.backend.unsupported("accessors = \"methods\""));
+        }
+        let fields = get_fields(data, attrs.backend, accessors)?;
+        if attrs.full_name.is_some() && !attrs.register {
+            return Err(darling::Error::custom(
+                "`full_name` can only be used together with `register`",
+            ));
+        }
+        // Only the messages of rust-protobuf have the descriptors with their full names.
+        if attrs.register && attrs.full_name.is_none() && !attrs.backend.implements_message() {
+            return Err(darling::Error::custom(format!(
+                "`full_name` is required for `register` with the `{}` backend",
+                attrs.backend.name()
+            )));
+        }
+        if attrs.deny_unknown_fields && fields.iter().any(|field| field.attrs.unknown_fields) {
+            return Err(darling::Error::custom(
+                "`deny_unknown_fields` cannot be used with `unknown_fields` fields",
+            ));
+        }
+
+        let backend = attrs.backend;
+        let used = [
+            ("deny_unknown_fields", attrs.deny_unknown_fields),
+            ("field_mask", attrs.field_mask),
+            ("merge", attrs.merge),
+            ("reuse_buffers", attrs.reuse_buffers),
+            ("wire", attrs.wire),
+        ];
+        if let Some((attribute, _)) = used
+            .iter()
+            .find(|(attribute, used)| *used && !backend.supports(attribute))
+        {
+            return Err(backend.unsupported(attribute));
+        }
+        if accessors == Accessors::Fields
+            && (attrs.field_mask || attrs.merge || attrs.reuse_buffers)
+        {
+            return Err(darling::Error::custom(
+                "`field_mask`, `merge` and `reuse_buffers` cannot be used with \
+                 `accessors = \"fields\"`",
+            ));
+        }
+
+        if attrs.wire {
+            let mut numbers = Vec::new();
+            for field in &fields {
+                field.check_wire()?;
+                if let Some(number) = field.attrs.number {
+                    if numbers.contains(&number) {
+                        return Err(darling::Error::custom(format!(
+                            "Duplicate field number {}",
+                            number
+                        )));
+                    }
+                    numbers.push(number);
+                }
+            }
+        } else if fields
+            .iter()
+            .any(|field| field.attrs.number.is_some() || field.attrs.packed)
+        {
+            return Err(darling::Error::custom(
+                "`number` and `packed` can only be used together with `wire`",
+            ));
+        }
 
+        let converted = fields
+            .iter()
+            .filter(|field| field.uses_trait())
+            .map(|field| field.ty.to_token_stream())
+            .collect::<Vec<_>>();
         Ok(Self {
             name,
+            generics: add_trait_bounds(generics, &converted),
             attrs,
             fields,
         })
     }
+
+    /// Returns `true` if `encode` writes the nested messages.
+    fn encodes_messages(&self) -> bool {
+        self.fields.iter().any(|field| {
+            !field.attrs.skip
+                && !field.attrs.from_pb_only
+                && !field.attrs.unknown_fields
+                && field.proto_type() == ProtoType::Message
+        })
+    }
+
+    /// Returns `true` if `decode` decodes the nested messages with their `decode`,
+    /// which may be recursive.
+    fn decodes_messages(&self) -> bool {
+        !self.attrs.deny_unknown_fields
+            && self.fields.iter().any(|field| {
+                !field.attrs.skip
+                    && !field.attrs.to_pb_only
+                    && !field.attrs.unknown_fields
+                    && field.proto_type() == ProtoType::Message
+            })
+    }
+
+    /// Makes the owned message mutable if the values of its fields are moved out of it.
+    fn impl_take_pb(&self) -> proc_macro2::TokenStream {
+        if self.fields.iter().any(ParsedField::reads_moved_value) {
+            quote! { let mut pb = pb; }
+        } else {
+            quote! {}
+        }
+    }
 }
 
-impl ProtobufConvertFieldAttrs {
-    fn impl_field_setter(&self, ident: &Ident) -> impl ToTokens {
-        let pb_getter = Ident::new(&format!("get_{}", ident), Span::call_site());
+impl ParsedField {
+    /// Checks that the field attributes are supported by the backend.
+    fn check_backend(&self) -> Result<(), darling::Error> {
+        let backend = self.backend;
+        let used = [
+            ("path", self.attrs.path.is_some()),
+            ("unknown_fields", self.attrs.unknown_fields),
+            ("enumeration", self.attrs.enumeration),
+            ("wrapper", self.attrs.wrapper),
+        ];
+        match used
+            .iter()
+            .find(|(attribute, used)| *used && !backend.supports(attribute))
+        {
+            Some((attribute, _)) => Err(backend.unsupported(attribute)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns `true` if the field type is converted with its `ProtobufConvert` implementation.
+    fn uses_trait(&self) -> bool {
+        !self.attrs.skip
+            && !self.attrs.unknown_fields
+            && !self.attrs.display_fromstr
+            && self.attrs.with.is_none()
+            && self.via.is_none()
+    }
+
+    /// Constructor of the pointer type unless the field is converted as `plain`.
+    fn pointer_constructor(&self, ty: &Type) -> Option<proc_macro2::TokenStream> {
+        if self.attrs.plain {
+            None
+        } else {
+            pointer_constructor(ty)
+        }
+    }
+
+    /// Intermediate messages of the nested protobuf path.
+    fn pb_parents(&self) -> &[Ident] {
+        &self.pb_path[..self.pb_path.len() - 1]
+    }
+
+    fn pb_field(&self) -> &Ident {
+        self.pb_path.last().unwrap()
+    }
+
+    /// Returns `true` if the fields of the protobuf structs are accessed directly.
+    fn uses_fields(&self) -> bool {
+        self.accessors == Accessors::Fields
+    }
+
+    /// Accessor method of the protobuf message or field, e.g. `get_x`.
+    fn pb_accessor(&self, prefix: &str, segment: &Ident) -> Ident {
+        let segment = self.backend.field_ident(segment);
+        Ident::new(&format!("{}_{}", prefix, segment), Span::call_site())
+    }
+
+    /// Getter method or struct field reading the protobuf field.
+    fn pb_getter(&self) -> Ident {
+        match &self.getter {
+            Some(getter) => getter.clone(),
+            None if self.uses_fields() => self.backend.field_ident(self.pb_field()),
+            None => self.pb_accessor("get", self.pb_field()),
+        }
+    }
+
+    /// Setter method or struct field writing the protobuf field.
+    fn pb_setter(&self) -> Ident {
+        match &self.setter {
+            Some(setter) => setter.clone(),
+            None if self.uses_fields() => self.backend.field_ident(self.pb_field()),
+            None => self.pb_accessor("set", self.pb_field()),
+        }
+    }
+
+    /// Directly accessed intermediate message containing the protobuf field.
+    fn pb_parent_ref(&self) -> proc_macro2::TokenStream {
+        self.pb_parents()
+            .iter()
+            .fold(quote! { pb }, |parent, segment| {
+                let segment = self.backend.field_ident(segment);
+                self.backend.message_ref(quote! { #parent.#segment })
+            })
+    }
+
+    /// Returns `T` if the field has type `Option<T>` and its presence is determined
+    /// by the presence of the protobuf messages or by the default protobuf value.
+    fn optional_inner_type(&self) -> Option<&Type> {
+        let inner = option_inner_type(&self.ty)?;
+        if self.pb_parents().is_empty()
+            && !self.attrs.default_as_none
+            && !self.attrs.wrapper
+            && self.pointer_constructor(inner).is_none()
+        {
+            None
+        } else {
+            Some(inner)
+        }
+    }
+
+    /// Type of the value converted from the protobuf field.
+    fn value_type(&self) -> &Type {
+        self.optional_inner_type().unwrap_or(&self.ty)
+    }
+
+    /// Returns `true` if the generated code compares the protobuf value with its default.
+    fn checks_default_value(&self) -> bool {
+        !self.attrs.skip
+            && !self.attrs.to_pb_only
+            && (self.attrs.default_as_none || self.default_value.is_some())
+    }
+
+    /// Returns `true` if the field is decoded from the value moved out of the message.
+    fn reads_moved_value(&self) -> bool {
+        !self.attrs.skip
+            && !self.attrs.to_pb_only
+            && !self.attrs.unknown_fields
+            && self.moves_value()
+    }
+
+    /// Returns `true` if the protobuf value is moved out of the message instead of cloning.
+    fn moves_value(&self) -> bool {
+        // Nested messages are shared by the fields and are not modified when reading them.
+        if !self.pb_parents().is_empty() || self.shared {
+            return false;
+        }
+        if self.uses_fields() {
+            return true;
+        }
+        if self.getter.is_some() || self.attrs.with.is_some() {
+            return false;
+        }
+
+        // rust-protobuf generates `take_x()` only for the strings, bytes, messages
+        // and repeated fields.
+        self.is_owned_pb_field()
+    }
+
+    /// Returns `true` if the protobuf field is a string, bytes, message or repeated field,
+    /// which have the `take_x()` and `mut_x()` accessors. Fields of the primitive types,
+    /// enumerations and the fields with the scalar `proto_type` are the scalar fields,
+    /// while the fields of the other types are assumed to be owned.
+    fn is_owned_pb_field(&self) -> bool {
+        let is_owned_type = |ty: &Type| match self.attrs.proto_type {
+            Some(proto_type) => !proto_type.is_scalar(),
+            None => !is_scalar_type(ty),
+        };
+        if self.attrs.wrapper {
+            return true;
+        }
+        match self.value_conversion() {
+            ValueConversion::Plain => is_owned_type(self.value_type()),
+            ValueConversion::Pointer(_) => is_owned_type(self.wire_value_type()),
+            ValueConversion::Via(via) => !is_scalar_type(via),
+            ValueConversion::Enumeration => false,
+            ValueConversion::Bytes(_)
+            | ValueConversion::Array(_)
+            | ValueConversion::Set(_)
+            | ValueConversion::DisplayFromStr(_) => true,
+        }
+    }
+
+    /// Converts the value of the protobuf field into the protobuf type of the struct field.
+    fn read_pb_field(&self, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        if self.moves_value() {
+            // Moved repeated fields are converted from `RepeatedField`.
+            Accessors::Fields.read_pb_field(value)
+        } else {
+            self.accessors.read_pb_field(value)
+        }
+    }
+
+    fn pb_value(&self) -> impl ToTokens {
+        let field = self.pb_getter();
+        if self.moves_value() && !self.uses_fields() {
+            let take = self.pb_accessor("take", self.pb_field());
+            return if self.attrs.wrapper {
+                quote! { pb.#take().value }
+            } else {
+                quote! { pb.#take() }
+            };
+        }
+        if self.uses_fields() {
+            // Wrappers are kept as messages by rust-protobuf.
+            let value = if self.attrs.wrapper && self.backend.implements_message() {
+                quote! { .value }
+            } else {
+                quote! {}
+            };
+            // Nested messages are shared by the fields, so their values are cloned.
+            return if !self.pb_parents().is_empty() || self.shared {
+                let parent = self.pb_parent_ref();
+                quote! { #parent.#field #value .clone() }
+            } else if value.is_empty() {
+                quote! { std::mem::take(&mut pb.#field) }
+            } else {
+                quote! { std::mem::take(&mut pb.#field).unwrap_or_default() #value }
+            };
+        }
+
+        let getters = self
+            .pb_parents()
+            .iter()
+            .map(|segment| self.pb_accessor("get", segment));
+        if self.attrs.wrapper {
+            quote! { pb #( .#getters() )* .#field().get_value().to_owned() }
+        } else {
+            quote! { pb #( .#getters() )* .#field().to_owned() }
+        }
+    }
+
+    fn value_conversion(&self) -> ValueConversion<'_> {
+        match &self.via {
+            Some(via) => ValueConversion::Via(via),
+            None if self.attrs.enumeration => ValueConversion::Enumeration,
+            None if self.attrs.plain => ValueConversion::Plain,
+            None if self.attrs.display_fromstr => {
+                ValueConversion::DisplayFromStr(self.value_type())
+            }
+            None => ValueConversion::new(self.value_type()),
+        }
+    }
+
+    fn length_error(&self, len: &Expr, actual: impl ToTokens) -> impl ToTokens {
+        let err_msg = format!(
+            "Invalid length of field `{}`: expected {{}}, got {{}}",
+            self.name
+        );
+        quote! {
+            anyhow::anyhow!(#err_msg, #len, #actual)
+        }
+    }
+
+    fn convert_from_pb(&self, value: impl ToTokens) -> impl ToTokens {
+        if let Some(with) = &self.attrs.with {
+            return quote! { #with::from_pb(#value)? };
+        }
+
+        let value = value.into_token_stream();
+        match self.value_conversion() {
+            ValueConversion::Plain => {
+                let value = self.read_pb_field(value);
+                quote! { ProtobufConvert::from_pb(#value)? }
+            }
+            ValueConversion::Pointer(constructor) => {
+                let value = self.read_pb_field(value);
+                quote! { #constructor(ProtobufConvert::from_pb(#value)?) }
+            }
+            ValueConversion::Bytes(len) => {
+                let value = self.read_pb_field(value);
+                let err = self.length_error(len, quote! { bytes.len() });
+                quote! {{
+                    let bytes: Vec<u8> = #value;
+                    std::convert::TryFrom::try_from(bytes.as_slice()).map_err(|_| #err)?
+                }}
+            }
+            ValueConversion::Array(len) => {
+                let err = self.length_error(len, quote! { items.len() });
+                quote! {{
+                    let items = #value
+                        .into_iter()
+                        .map(ProtobufConvert::from_pb)
+                        .collect::<std::result::Result<Vec<_>, _>>()?;
+                    std::convert::TryFrom::try_from(items).map_err(|items: Vec<_>| #err)?
+                }}
+            }
+            ValueConversion::Set(ty) => {
+                self.collect_set(ty, value, quote! { ProtobufConvert::from_pb(item)? })
+            }
+            ValueConversion::Via(via) => {
+                let value = self.read_pb_field(value);
+                let err_msg = format!("Value {{}} of field `{}` is out of range", self.name);
+                quote! {{
+                    let value: #via = ProtobufConvert::from_pb(#value)?;
+                    std::convert::TryFrom::try_from(value)
+                        .map_err(|_| anyhow::anyhow!(#err_msg, value))?
+                }}
+            }
+            ValueConversion::DisplayFromStr(ty) => {
+                let err_msg = format!("Failed to parse field `{}`: {{}}", join_path(&self.pb_path));
+                quote! {
+                    <#ty as std::str::FromStr>::from_str(&#value)
+                        .map_err(|e| anyhow::anyhow!(#err_msg, e))?
+                }
+            }
+            // rust-protobuf 2.x returns the generated enums, which are copied out of the message.
+            ValueConversion::Enumeration if self.backend == Backend::Protobuf => {
+                quote! { ProtobufConvert::from_pb(#value)? }
+            }
+            ValueConversion::Enumeration if self.backend == Backend::Protobuf3 => {
+                let err_msg = format!("Unknown value {{}} of enum field `{}`", self.name);
+                quote! {{
+                    let value = protobuf::EnumOrUnknown::enum_value(&#value)
+                        .map_err(|value| anyhow::anyhow!(#err_msg, value))?;
+                    ProtobufConvert::from_pb(value)?
+                }}
+            }
+            ValueConversion::Enumeration => {
+                let err_msg = format!("Unknown value {{}} of enum field `{}`", self.name);
+                quote! {{
+                    let value: i32 = #value;
+                    let value = std::convert::TryFrom::try_from(value)
+                        .map_err(|_| anyhow::anyhow!(#err_msg, value))?;
+                    ProtobufConvert::from_pb(value)?
+                }}
+            }
+        }
+    }
+
+    /// Collects the `items` converted with the `item` expression into the set of type `ty`.
+    fn collect_set(
+        &self,
+        ty: &Type,
+        items: proc_macro2::TokenStream,
+        item: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let insert = if self.attrs.deny_duplicates {
+            let err_msg = format!("Duplicate element in field `{}` at index {{}}", self.name);
+            quote! {
+                for (index, item) in #items.into_iter().enumerate() {
+                    if !set.insert(#item) {
+                        return Err(anyhow::anyhow!(#err_msg, index));
+                    }
+                }
+            }
+        } else {
+            quote! {
+                for item in #items {
+                    set.insert(#item);
+                }
+            }
+        };
+        quote! {{
+            let mut set = <#ty as Default>::default();
+            #insert
+            set
+        }}
+    }
+
+    fn convert_to_pb(&self, value: impl ToTokens) -> impl ToTokens {
+        if let Some(with) = &self.attrs.with {
+            // Modules converting the fields of directly accessed structs return the field type.
+            return if self.uses_fields() {
+                quote! { #with::to_pb(#value) }
+            } else {
+                quote! { #with::to_pb(#value).into() }
+            };
+        }
+
+        let accessors = self.accessors;
+        match self.value_conversion() {
+            ValueConversion::Plain => {
+                accessors.write_pb_field(quote! { ProtobufConvert::to_pb(#value) }, true)
+            }
+            ValueConversion::Pointer(_) => {
+                accessors.write_pb_field(quote! { ProtobufConvert::to_pb(&**#value) }, true)
+            }
+            ValueConversion::Bytes(_) => {
+                accessors.write_pb_field(quote! { <[u8]>::to_vec(#value) }, false)
+            }
+            ValueConversion::Array(_) => accessors.write_pb_field(
+                quote! {
+                    <[_]>::iter(#value)
+                        .map(ProtobufConvert::to_pb)
+                        .collect::<Vec<_>>()
+                },
+                true,
+            ),
+            ValueConversion::Set(_) => accessors.write_pb_field(
+                quote! {
+                    IntoIterator::into_iter(#value)
+                        .map(ProtobufConvert::to_pb)
+                        .collect::<Vec<_>>()
+                },
+                true,
+            ),
+            ValueConversion::Via(via) => {
+                let converted =
+                    accessors.write_pb_field(quote! { ProtobufConvert::to_pb(&value) }, true);
+                // The value must fit into the wire type, so narrowing `via` types are rejected
+                // by the missing `From` implementation. Pointer-sized integers are checked
+                // to fit into the 64-bit types at compile time instead.
+                let value_type = self.value_type();
+                let value = if is_pointer_sized_widening(value_type, via) {
+                    quote! {{
+                        const _: () = assert!(
+                            std::mem::size_of::<#value_type>() <= std::mem::size_of::<#via>()
+                        );
+                        *#value as #via
+                    }}
+                } else {
+                    quote! { <#via as std::convert::From<_>>::from(Clone::clone(#value)) }
+                };
+                quote! {{
+                    let value = #value;
+                    #converted
+                }}
+            }
+            ValueConversion::DisplayFromStr(_) => {
+                accessors.write_pb_field(quote! { ToString::to_string(#value) }, true)
+            }
+            ValueConversion::Enumeration if self.backend == Backend::Protobuf => {
+                accessors.write_pb_field(quote! { ProtobufConvert::to_pb(#value) }, false)
+            }
+            ValueConversion::Enumeration if self.backend == Backend::Protobuf3 => {
+                quote! { protobuf::EnumOrUnknown::new(ProtobufConvert::to_pb(#value)) }
+            }
+            ValueConversion::Enumeration => accessors
+                .write_pb_field(quote! { i32::from(ProtobufConvert::to_pb(#value)) }, false),
+        }
+    }
+
+    /// Returns `true` if the field is a `Box`, which owns its value.
+    fn is_box(&self) -> bool {
+        type_arguments(self.value_type()).is_some_and(|(name, _)| name == "Box")
+    }
+
+    /// Converts the owned value, moving it into the protobuf value where possible.
+    fn convert_into_pb(&self, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let accessors = self.accessors;
+        match self.value_conversion() {
+            _ if self.attrs.with.is_some() => {}
+            ValueConversion::Plain => {
+                return accessors.write_pb_field(quote! { ProtobufConvert::into_pb(#value) }, true);
+            }
+            ValueConversion::Pointer(_) if self.is_box() => {
+                return accessors
+                    .write_pb_field(quote! { ProtobufConvert::into_pb(*#value) }, true);
+            }
+            ValueConversion::Array(_) | ValueConversion::Set(_) => {
+                return accessors.write_pb_field(
+                    quote! {
+                        IntoIterator::into_iter(#value)
+                            .map(ProtobufConvert::into_pb)
+                            .collect::<Vec<_>>()
+                    },
+                    true,
+                );
+            }
+            // Shared pointers and the other conversions borrow the value.
+            _ => {}
+        }
+        self.convert_to_pb(quote! { &#value }).into_token_stream()
+    }
+
+    /// Checks of the intermediate messages presence with the corresponding error messages.
+    fn pb_parents_checks(&self) -> Vec<(proc_macro2::TokenStream, String)> {
+        let mut checks = Vec::new();
+        let mut parent = quote! { pb };
+        for (i, segment) in self.pb_parents().iter().enumerate() {
+            let err_msg = format!(
+                "Missing message `{}` required by field `{}`",
+                join_path(&self.pb_path[..=i]),
+                self.name
+            );
+            if self.uses_fields() {
+                // Absent message fields are read as the default instances.
+                let segment = self.backend.field_ident(segment);
+                checks.push((quote! { #parent.#segment.is_some() }, err_msg));
+                parent = self.backend.message_ref(quote! { #parent.#segment });
+            } else {
+                let has = self.pb_accessor("has", segment);
+                let get = self.pb_accessor("get", segment);
+                checks.push((quote! { #parent.#has() }, err_msg));
+                parent = quote! { #parent.#get() };
+            }
+        }
+        checks
+    }
+
+    /// Converts the protobuf field value, assuming that the intermediate messages are present.
+    fn impl_value_setter(&self) -> impl ToTokens {
+        let pb_value = self.pb_value();
+        if self.attrs.default_as_none {
+            let value = self.convert_from_pb(quote! { value });
+            quote! {{
+                let value = #pb_value;
+                if is_default_value(&value) {
+                    None
+                } else {
+                    Some(#value)
+                }
+            }}
+        } else if let Some(default_value) = &self.default_value {
+            let value = self.convert_from_pb(quote! { value });
+            quote! {{
+                let value = #pb_value;
+                if is_default_value(&value) {
+                    #default_value
+                } else {
+                    #value
+                }
+            }}
+        } else if self.optional_inner_type().is_some() {
+            let value = self.convert_from_pb(pb_value);
+            quote! { Some(#value) }
+        } else {
+            let value = self.convert_from_pb(pb_value);
+            quote! { #value }
+        }
+    }
+
+    fn impl_field_setter(&self) -> impl ToTokens {
+        let ident = &self.name;
+        let value = self.impl_field_init();
+        quote! { #ident: #value, }
+    }
+
+    /// Initial value of the field decoded from `pb`.
+    fn impl_field_init(&self) -> proc_macro2::TokenStream {
+        if self.attrs.skip || self.attrs.to_pb_only {
+            // Default setter for the skipped fields, computed fields are set afterwards.
+            return match &self.default_value {
+                Some(default_value) => quote! { #default_value },
+                None => quote! { Default::default() },
+            };
+        }
+        if self.attrs.unknown_fields {
+            return if self.backend == Backend::Protobuf3 {
+                quote! { pb.unknown_fields().clone() }
+            } else {
+                quote! { pb.get_unknown_fields().clone() }
+            };
+        }
+
+        self.impl_field_value()
+    }
+
+    /// Decodes the field from `pb` into the existing value, reusing the allocations
+    /// of its strings, vectors and nested derived types.
+    fn impl_field_from_pb_into(&self) -> proc_macro2::TokenStream {
+        let ident = &self.name;
+        let reusable = !self.attrs.skip
+            && !self.attrs.to_pb_only
+            && !self.attrs.unknown_fields
+            && !self.attrs.wrapper
+            && !self.attrs.required
+            && !self.attrs.default_as_none
+            && self.attrs.with.is_none()
+            && self.default_value.is_none()
+            && self.optional_inner_type().is_none();
+        let target = match self.value_conversion() {
+            ValueConversion::Plain if reusable && !is_scalar_type(self.value_type()) => {
+                quote! { &mut self.#ident }
+            }
+            ValueConversion::Pointer(_) if reusable && self.is_box() => {
+                quote! { &mut *self.#ident }
+            }
+            // Elements of the arrays are decoded in place.
+            ValueConversion::Array(len) if reusable => {
+                let value = self.pb_value();
+                let err = self.length_error(len, quote! { items.len() });
+                return quote! {{
+                    let items = #value;
+                    if items.len() != #len {
+                        return Err(#err);
+                    }
+                    for (target, item) in self.#ident.iter_mut().zip(items) {
+                        ProtobufConvert::from_pb_into(target, item)?;
+                    }
+                }};
+            }
+            _ => {
+                let value = self.impl_field_init();
+                return quote! { self.#ident = #value; };
+            }
+        };
+
+        let value = self.read_pb_field(self.pb_value().into_token_stream());
+        quote! { ProtobufConvert::from_pb_into(#target, #value)?; }
+    }
+
+    /// Decodes the field value from `pb`, checking the presence of the intermediate messages.
+    fn impl_field_value(&self) -> proc_macro2::TokenStream {
+        let mut checks = self.pb_parents_checks();
+        let value = self.impl_value_setter();
+        if let Some(inner) = self.optional_inner_type() {
+            if self.attrs.wrapper || self.pointer_constructor(inner).is_some() {
+                // Wrappers and pointers are used for the message fields,
+                // so their presence can be checked.
+                if self.uses_fields() {
+                    let pb_field = self.pb_getter();
+                    let parent = self.pb_parent_ref();
+                    checks.push((quote! { #parent.#pb_field.is_some() }, String::new()));
+                } else {
+                    let has = self.pb_accessor("has", self.pb_field());
+                    let getters = self
+                        .pb_parents()
+                        .iter()
+                        .map(|segment| self.pb_accessor("get", segment));
+                    checks.push((quote! { pb #( .#getters() )* .#has() }, String::new()));
+                }
+            }
+            if checks.is_empty() {
+                return quote! { #value };
+            }
+
+            // Absent messages mean that the value is absent as well.
+            let first = &checks[0].0;
+            let rest = checks[1..].iter().map(|(condition, _)| condition);
+            quote! {
+                if #first #( && #rest )* {
+                    #value
+                } else {
+                    None
+                }
+            }
+        } else if self.attrs.required {
+            let checks = checks.iter().map(|(condition, err_msg)| {
+                quote! {
+                    if !#condition {
+                        return Err(anyhow::anyhow!(#err_msg));
+                    }
+                }
+            });
+            quote! {{
+                #( #checks )*
+                #value
+            }}
+        } else {
+            quote! { #value }
+        }
+    }
+
+    /// Assigns the computed value of the field once the rest of the struct is decoded.
+    fn impl_field_compute(&self) -> Option<impl ToTokens> {
+        let ident = &self.name;
+        self.compute.as_ref().map(|compute| {
+            quote! { inner.#ident = (#compute)(&inner); }
+        })
+    }
+
+    fn impl_field_getter(&self) -> impl ToTokens {
+        if self.attrs.skip || self.attrs.from_pb_only {
+            // Skipped getter does nothing.
+            return quote! {};
+        }
+        if self.attrs.unknown_fields {
+            let ident = &self.name;
+            return quote! { *msg.mut_unknown_fields() = Clone::clone(&self.#ident); };
+        }
+
+        self.impl_pb_setter_call(false, |value| self.convert_to_pb(value).into_token_stream())
+    }
+
+    /// Writes the field value into the cleared protobuf message, reusing the allocations
+    /// of the strings, bytes, messages and repeated fields.
+    fn impl_field_to_pb_into(&self) -> proc_macro2::TokenStream {
+        if self.attrs.skip || self.attrs.from_pb_only {
+            return quote! {};
+        }
+        let reusable = !self.attrs.unknown_fields
+            && !self.attrs.wrapper
+            && self.attrs.with.is_none()
+            && self.setter.is_none();
+        let fill = |value: proc_macro2::TokenStream| match self.value_conversion() {
+            // Other vectors are mapped to `RepeatedField` rather than to their `ProtoStruct`.
+            ValueConversion::Plain
+                if self.is_owned_pb_field() && !is_repeated_type(self.value_type()) =>
+            {
+                Some(quote! { ProtobufConvert::to_pb_into(#value, target); })
+            }
+            ValueConversion::Pointer(_) if self.is_owned_pb_field() => {
+                Some(quote! { ProtobufConvert::to_pb_into(&**#value, target); })
+            }
+            ValueConversion::Bytes(_) => Some(quote! { target.extend_from_slice(#value); }),
+            ValueConversion::Array(_) | ValueConversion::Set(_) => Some(quote! {
+                for item in #value {
+                    ProtobufConvert::to_pb_into(item, target.push_default());
+                }
+            }),
+            _ => None,
+        };
+        let fill = match fill(quote! { value }) {
+            Some(fill) if reusable => fill,
+            _ => return self.impl_field_getter().into_token_stream(),
+        };
+
+        let ident = &self.name;
+        let source = match &self.compute {
+            Some(compute) => quote! { (#compute)(self) },
+            None => quote! { self.#ident },
+        };
+        let parents = self
+            .pb_parents()
+            .iter()
+            .map(|segment| self.pb_accessor("mut", segment));
+        let mut_field = self.pb_accessor("mut", self.pb_field());
+        let target = quote! { msg #( .#parents() )* .#mut_field() };
+        if self.optional_inner_type().is_some() {
+            quote! {
+                if let Some(value) = &#source {
+                    let target = #target;
+                    #fill
+                }
+            }
+        } else {
+            quote! {{
+                let value = &#source;
+                let target = #target;
+                #fill
+            }}
+        }
+    }
+
+    /// Moves the field value into the protobuf message.
+    fn impl_field_into_pb(&self) -> impl ToTokens {
+        if self.attrs.skip || self.attrs.from_pb_only {
+            return quote! {};
+        }
+        if self.attrs.unknown_fields {
+            let ident = &self.name;
+            return quote! { *msg.mut_unknown_fields() = self.#ident; };
+        }
+
+        self.impl_pb_setter_call(true, |value| self.convert_into_pb(value))
+    }
+
+    /// Sets the protobuf field to the field value converted with `convert`.
+    /// `owned` specifies whether the field value is moved out of `self`.
+    fn impl_pb_setter_call(
+        &self,
+        owned: bool,
+        convert: impl Fn(proc_macro2::TokenStream) -> proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let ident = &self.name;
+        let (source, borrow) = match &self.compute {
+            Some(compute) if owned => (quote! { (#compute)(&self) }, quote! {}),
+            Some(compute) => (quote! { (#compute)(self) }, quote! { & }),
+            None if owned => (quote! { self.#ident }, quote! {}),
+            None => (quote! { self.#ident }, quote! { & }),
+        };
+
+        if self.uses_fields() {
+            let parent = self
+                .pb_parents()
+                .iter()
+                .fold(quote! { msg }, |parent, segment| {
+                    let segment = self.backend.field_ident(segment);
+                    self.backend.message_mut(quote! { #parent.#segment })
+                });
+            let pb_field = self.pb_setter();
+            let target = if self.attrs.wrapper && self.backend.implements_message() {
+                let wrapper = self.backend.message_mut(quote! { #parent.#pb_field });
+                quote! { #wrapper.value }
+            } else {
+                quote! { #parent.#pb_field }
+            };
+            return if self.optional_inner_type().is_some() {
+                let value = convert(quote! { value });
+                quote! {
+                    if let Some(value) = #borrow #source {
+                        #target = #value;
+                    }
+                }
+            } else {
+                let value = convert(quote! { #borrow #source });
+                quote! { #target = #value; }
+            };
+        }
+
+        let parents = self
+            .pb_parents()
+            .iter()
+            .map(|segment| self.pb_accessor("mut", segment));
+        let pb_setter = if self.attrs.wrapper {
+            let pb_field = self.pb_accessor("mut", self.pb_field());
+            quote! { #pb_field().set_value }
+        } else {
+            let pb_setter = self.pb_setter();
+            quote! { #pb_setter }
+        };
+
+        if self.optional_inner_type().is_some() {
+            let value = convert(quote! { value });
+            quote! {
+                if let Some(value) = #borrow #source {
+                    msg #( .#parents() )* .#pb_setter(#value);
+                }
+            }
+        } else {
+            let value = convert(quote! { #borrow #source });
+            quote! {
+                msg #( .#parents() )* .#pb_setter(#value);
+            }
+        }
+    }
+
+    /// Returns `true` if the paths of the field mask can select the subfields of the value.
+    fn supports_nested_mask(&self) -> bool {
+        self.attrs.with.is_none()
+            && !self.attrs.wrapper
+            && matches!(
+                self.value_conversion(),
+                ValueConversion::Plain | ValueConversion::Pointer(_)
+            )
+    }
+
+    /// Returns the declarations of the field mask state, the matching of the mask path
+    /// against the field and the setter of the selected field.
+    fn impl_field_masked_getter(
+        &self,
+    ) -> Option<(
+        proc_macro2::TokenStream,
+        proc_macro2::TokenStream,
+        proc_macro2::TokenStream,
+    )> {
+        if self.attrs.skip || self.attrs.from_pb_only || self.attrs.unknown_fields {
+            return None;
+        }
+
+        let (selected, nested) = self.mask_idents();
+        let pb_path = join_path(&self.pb_path);
+        let full_setter =
+            self.impl_pb_setter_call(false, |value| self.convert_to_pb(value).into_token_stream());
+        let (declarations, matching) = self.impl_field_mask_matching(self.supports_nested_mask());
+
+        if !self.supports_nested_mask() {
+            let setter = quote! {
+                if #selected {
+                    #full_setter
+                }
+            };
+            return Some((declarations, matching, setter));
+        }
+
+        let nested_setter = self.impl_pb_setter_call(false, |value| {
+            let value = match self.value_conversion() {
+                ValueConversion::Pointer(_) => quote! { &**#value },
+                _ => value,
+            };
+            quote! {
+                ProtobufConvert::to_pb_masked(#value, &mask)
+                    .map_err(|e| nested_mask_error(e, #pb_path))?
+                    .into()
+            }
+        });
+        // Paths of the absent values are checked against the field type.
+        let absent_check = if self.optional_inner_type().is_some() {
+            let ident = &self.name;
+            let source = match &self.compute {
+                Some(compute) => quote! { (#compute)(self) },
+                None => quote! { self.#ident },
+            };
+            let ty = self.masked_value_type();
+            quote! {
+                if #source.is_none() {
+                    <#ty>::check_mask_paths(mask.get_paths())
+                        .map_err(|e| nested_mask_error(e, #pb_path))?;
+                }
+            }
+        } else {
+            quote! {}
+        };
+        let setter = quote! {
+            if #selected {
+                #full_setter
+            } else if !#nested.is_empty() {
+                let mut mask = protobuf::well_known_types::FieldMask::new();
+                mask.set_paths(#nested.into());
+                #absent_check
+                #nested_setter
+            }
+        };
+        Some((declarations, matching, setter))
+    }
+
+    /// Type of the value whose subfields are selected by the nested mask paths.
+    fn masked_value_type(&self) -> &Type {
+        match self.value_conversion() {
+            ValueConversion::Pointer(_) => type_arguments(self.value_type()).unwrap().1[0],
+            _ => self.value_type(),
+        }
+    }
+
+    /// Returns the declarations of the field mask state, the matching of the mask path
+    /// against the field and the check of the nested paths against the field type.
+    fn impl_field_mask_check(
+        &self,
+    ) -> Option<(
+        proc_macro2::TokenStream,
+        proc_macro2::TokenStream,
+        proc_macro2::TokenStream,
+    )> {
+        if self.attrs.skip || self.attrs.from_pb_only || self.attrs.unknown_fields {
+            return None;
+        }
+
+        let (declarations, matching) = self.impl_field_mask_matching(self.supports_nested_mask());
+        if !self.supports_nested_mask() {
+            return Some((declarations, matching, quote! {}));
+        }
+        let (_, nested) = self.mask_idents();
+        let pb_path = join_path(&self.pb_path);
+        let ty = self.masked_value_type();
+        let check = quote! {
+            if !#nested.is_empty() {
+                <#ty>::check_mask_paths(&#nested).map_err(|e| nested_mask_error(e, #pb_path))?;
+            }
+        };
+        Some((declarations, matching, check))
+    }
+
+    /// Names of the variables holding whether the whole field is selected by the mask
+    /// and the mask paths of its subfields.
+    fn mask_idents(&self) -> (Ident, Ident) {
+        (
+            Ident::new(&format!("selected_{}", self.name), Span::call_site()),
+            Ident::new(&format!("nested_{}", self.name), Span::call_site()),
+        )
+    }
+
+    /// Returns the declarations of the field mask state and the matching of the mask path
+    /// against the field.
+    fn impl_field_mask_matching(
+        &self,
+        nested_mask: bool,
+    ) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+        let pb_path = join_path(&self.pb_path);
+        let (selected, nested) = self.mask_idents();
+
+        if !nested_mask {
+            let declarations = quote! { let mut #selected = false; };
+            let matching = quote! {
+                if field_mask_tail(path, #pb_path) == Some("") {
+                    #selected = true;
+                    matched = true;
+                }
+            };
+            return (declarations, matching);
+        }
+
+        let declarations = quote! {
+            let mut #selected = false;
+            let mut #nested = Vec::new();
+        };
+        let matching = quote! {
+            match field_mask_tail(path, #pb_path) {
+                Some("") => {
+                    #selected = true;
+                    matched = true;
+                }
+                Some(tail) => {
+                    #nested.push(tail.to_owned());
+                    matched = true;
+                }
+                None => {}
+            }
+        };
+        (declarations, matching)
+    }
+
+    /// Returns `true` if the field is merged with the `merge_from_pb` of its type
+    /// rather than replaced.
+    fn supports_nested_merge(&self) -> bool {
+        self.attrs.with.is_none()
+            && !self.attrs.wrapper
+            && self.default_value.is_none()
+            && self.optional_inner_type().is_none()
+            && matches!(self.value_conversion(), ValueConversion::Plain)
+    }
+
+    /// Checks whether the protobuf field is set, i.e. the intermediate messages are present
+    /// and the field itself is present or has a non-default value.
+    fn impl_field_presence(&self) -> proc_macro2::TokenStream {
+        let checks = self
+            .pb_parents_checks()
+            .into_iter()
+            .map(|(condition, _)| condition);
+        let getters = self
+            .pb_parents()
+            .iter()
+            .map(|segment| self.pb_accessor("get", segment));
+        let parent = quote! { pb #( .#getters() )* };
+        // Optional wrappers and pointers are read from the message fields, and the nested
+        // messages are present even if they are empty.
+        let is_message = self
+            .optional_inner_type()
+            .is_some_and(|inner| self.attrs.wrapper || self.pointer_constructor(inner).is_some())
+            || self.is_singular_message();
+        let field = if is_message {
+            let has = self.pb_accessor("has", self.pb_field());
+            quote! { #parent.#has() }
+        } else {
+            let getter = self.pb_getter();
+            quote! { #parent.#getter().is_set() }
+        };
+        quote! { #( #checks && )* #field }
+    }
+
+    /// Returns `true` if the field is mapped to a non-repeated protobuf message field.
+    fn is_singular_message(&self) -> bool {
+        self.attrs.with.is_none()
+            && self.getter.is_none()
+            && option_inner_type(&self.ty).is_none()
+            && matches!(
+                self.value_conversion(),
+                ValueConversion::Plain | ValueConversion::Pointer(_)
+            )
+            && self.repeated_elem_type().is_none()
+            && self.proto_type() == ProtoType::Message
+            && type_arguments(self.wire_value_type()).is_some_and(|(_, args)| args.is_empty())
+    }
+
+    /// Returns the declarations of the field mask state, the matching of the mask path
+    /// against the field, the merge of the field selected by the mask and the merge
+    /// of the field set in the message.
+    fn impl_field_merge(
+        &self,
+    ) -> Option<(
+        proc_macro2::TokenStream,
+        proc_macro2::TokenStream,
+        proc_macro2::TokenStream,
+        proc_macro2::TokenStream,
+    )> {
+        if self.attrs.skip || self.attrs.to_pb_only || self.attrs.unknown_fields {
+            return None;
+        }
+
+        let ident = &self.name;
+        let (selected, nested) = self.mask_idents();
+        let presence = self.impl_field_presence();
+        let value = self.impl_field_value();
+        let (declarations, matching) = self.impl_field_mask_matching(self.supports_nested_merge());
+
+        if !self.supports_nested_merge() {
+            let masked_merge = quote! {
+                if #selected {
+                    self.#ident = #value;
+                }
+            };
+            let present_merge = quote! {
+                if #presence {
+                    self.#ident = #value;
+                }
+            };
+            return Some((declarations, matching, masked_merge, present_merge));
+        }
+
+        let pb_value = self.pb_value();
+        let pb_path = join_path(&self.pb_path);
+        let masked_merge = quote! {
+            if #selected {
+                self.#ident = #value;
+            } else if !#nested.is_empty() {
+                let mut mask = protobuf::well_known_types::FieldMask::new();
+                mask.set_paths(#nested.into());
+                ProtobufConvert::merge_from_pb(&mut self.#ident, #pb_value, Some(&mask))
+                    .map_err(|e| nested_mask_error(e, #pb_path))?;
+            }
+        };
+        let present_merge = quote! {
+            if #presence {
+                ProtobufConvert::merge_from_pb(&mut self.#ident, #pb_value, None)?;
+            }
+        };
+        Some((declarations, matching, masked_merge, present_merge))
+    }
+
+    /// Checks that the field can be encoded directly on the wire.
+    fn check_wire(&self) -> Result<(), darling::Error> {
+        if self.attrs.skip || self.attrs.unknown_fields {
+            return Ok(());
+        }
+        let used = [
+            ("path", self.attrs.path.is_some()),
+            ("with", self.attrs.with.is_some()),
+            ("via", self.via.is_some()),
+            ("display_fromstr", self.attrs.display_fromstr),
+            ("wrapper", self.attrs.wrapper),
+        ];
+        if let Some((attribute, _)) = used.iter().find(|(_, used)| *used) {
+            return Err(darling::Error::custom(format!(
+                "`{}` cannot be used with `wire`",
+                attribute
+            )));
+        }
+
+        match self.attrs.number {
+            Some(number) if number == 0 || number > MAX_FIELD_NUMBER => {
+                return Err(darling::Error::custom(format!(
+                    "Invalid field number {} of field `{}`",
+                    number, self.name
+                )));
+            }
+            Some(_) => {}
+            None => {
+                return Err(darling::Error::custom(format!(
+                    "`number` is required for field `{}` of the `wire` struct",
+                    self.name
+                )));
+            }
+        }
+        if self.attrs.proto_type.is_none()
+            && !self.attrs.enumeration
+            && infer_proto_type(self.wire_value_type()).is_none()
+        {
+            return Err(darling::Error::custom(format!(
+                "Cannot infer the protobuf type of field `{}`, specify it with `proto_type`, \
+                 e.g. `proto_type = \"message\"` or `proto_type = \"int32\"`",
+                self.name
+            )));
+        }
+        if self.attrs.packed
+            && (self.repeated_elem_type().is_none() || !self.proto_type().is_scalar())
+        {
+            return Err(darling::Error::custom(
+                "`packed` can only be used with the repeated fields of scalar types",
+            ));
+        }
+        // The decoded messages cannot be compared with the default protobuf messages.
+        if self.proto_type() == ProtoType::Message
+            && (self.attrs.default_as_none || self.default_value.is_some())
+        {
+            return Err(darling::Error::custom(
+                "`default_as_none` and `default_value` cannot be used with the message fields \
+                 of `wire` structs",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Type of the elements if the field is mapped to a repeated field.
+    fn repeated_elem_type(&self) -> Option<&Type> {
+        match self.value_conversion() {
+            ValueConversion::Array(_) => match self.value_type() {
+                Type::Array(array) => Some(&array.elem),
+                _ => None,
+            },
+            ValueConversion::Set(ty) => type_arguments(ty).map(|(_, args)| args[0]),
+            ValueConversion::Plain if is_repeated_type(self.value_type()) => {
+                type_arguments(self.value_type()).map(|(_, args)| args[0])
+            }
+            _ => None,
+        }
+    }
+
+    /// Type of the protobuf field or of its elements, which is inferred from the Rust type
+    /// unless specified with `proto_type`. The types which cannot be inferred are assumed
+    /// to be messages, which is only allowed for the fields of the `wire` structs
+    /// with `proto_type = "message"`.
+    fn proto_type(&self) -> ProtoType {
+        if let Some(proto_type) = self.attrs.proto_type {
+            return proto_type;
+        }
+        if self.attrs.enumeration {
+            return ProtoType::Enum;
+        }
+        infer_proto_type(self.wire_value_type()).unwrap_or(ProtoType::Message)
+    }
+
+    /// Type of the value written on the wire, i.e. of the elements of the repeated fields
+    /// or of the pointee.
+    fn wire_value_type(&self) -> &Type {
+        match self.value_conversion() {
+            ValueConversion::Pointer(_) => type_arguments(self.value_type()).unwrap().1[0],
+            _ => self
+                .repeated_elem_type()
+                .unwrap_or_else(|| self.value_type()),
+        }
+    }
+
+    /// Checks the number and the type of the field against the message descriptor.
+    fn impl_field_schema_check(&self) -> Option<proc_macro2::TokenStream> {
+        if self.attrs.skip || self.attrs.unknown_fields {
+            return None;
+        }
+        let number = self.attrs.number?;
+        let name = self.pb_field().to_string();
+        let proto_type = self.proto_type();
+        let descriptor_type = proto_type.descriptor_type();
+        let repeated = self.repeated_elem_type().is_some();
+        let packed_check = if repeated && proto_type.is_scalar() {
+            let packed = self.attrs.packed;
+            quote! { check_wire_packed(descriptor, #name, #packed); }
+        } else {
+            quote! {}
+        };
+        Some(quote! {
+            check_wire_field(descriptor, #name, #number, #descriptor_type, #repeated);
+            #packed_check
+        })
+    }
+
+    /// Returns the field number, the expression computing the encoded length of the field
+    /// and the statements writing it.
+    fn impl_field_wire_encoding(
+        &self,
+    ) -> Option<(u32, proc_macro2::TokenStream, proc_macro2::TokenStream)> {
+        if self.attrs.skip || self.attrs.from_pb_only || self.attrs.unknown_fields {
+            return None;
+        }
+
+        let number = self.attrs.number?;
+        let proto_type = self.proto_type();
+        let wire_type = proto_type.wire_type();
+        let convert = match proto_type {
+            ProtoType::Message => quote! {},
+            _ => {
+                let value = proto_type.convert(quote! { value });
+                quote! { let value = #value; }
+            }
+        };
+        let value_len = proto_type.encoded_len(quote! { value });
+        let write_value = proto_type.write(quote! { value });
+
+        let (len, write) = if self.repeated_elem_type().is_none() {
+            let deref = match self.value_conversion() {
+                ValueConversion::Pointer(_) => quote! { let value = &**value; },
+                _ => quote! {},
+            };
+            match proto_type.is_default(quote! { value }) {
+                Some(is_default) => (
+                    quote! {{
+                        #deref
+                        #convert
+                        if #is_default {
+                            0
+                        } else {
+                            protobuf::rt::tag_size(#number) + #value_len
+                        }
+                    }},
+                    quote! {
+                        #deref
+                        #convert
+                        if !(#is_default) {
+                            os.write_tag(#number, #wire_type)?;
+                            #write_value
+                        }
+                    },
+                ),
+                None => (
+                    quote! {{
+                        #deref
+                        protobuf::rt::tag_size(#number) + #value_len
+                    }},
+                    quote! {
+                        #deref
+                        os.write_tag(#number, #wire_type)?;
+                        #write_value
+                    },
+                ),
+            }
+        } else if self.attrs.packed {
+            let data_len = quote! {
+                IntoIterator::into_iter(value)
+                    .map(|value| {
+                        #convert
+                        #value_len
+                    })
+                    .sum::<u32>()
+            };
+            (
+                quote! {{
+                    let data_len = #data_len;
+                    if data_len == 0 {
+                        0
+                    } else {
+                        protobuf::rt::tag_size(#number)
+                            + protobuf::rt::compute_raw_varint32_size(data_len)
+                            + data_len
+                    }
+                }},
+                quote! {
+                    let data_len = #data_len;
+                    if data_len != 0 {
+                        os.write_tag(#number, protobuf::wire_format::WireTypeLengthDelimited)?;
+                        os.write_raw_varint32(data_len)?;
+                        for value in value {
+                            #convert
+                            #write_value
+                        }
+                    }
+                },
+            )
+        } else {
+            (
+                quote! {
+                    IntoIterator::into_iter(value)
+                        .map(|value| {
+                            #convert
+                            protobuf::rt::tag_size(#number) + #value_len
+                        })
+                        .sum::<u32>()
+                },
+                quote! {
+                    for value in value {
+                        #convert
+                        os.write_tag(#number, #wire_type)?;
+                        #write_value
+                    }
+                },
+            )
+        };
+
+        let ident = &self.name;
+        let source = match &self.compute {
+            Some(compute) => quote! { &(#compute)(self) },
+            None => quote! { &self.#ident },
+        };
+        if self.optional_inner_type().is_some() {
+            Some((
+                number,
+                quote! {
+                    match #source {
+                        Some(value) => #len,
+                        None => 0,
+                    }
+                },
+                quote! {
+                    if let Some(value) = #source {
+                        #write
+                    }
+                },
+            ))
+        } else {
+            Some((
+                number,
+                quote! {{
+                    let value = #source;
+                    #len
+                }},
+                quote! {{
+                    let value = #source;
+                    #write
+                }},
+            ))
+        }
+    }
+
+    /// Returns the declaration of the variable holding the decoded protobuf value,
+    /// the field number with the statements reading the field and the value of the field.
+    /// `strict` specifies whether the nested messages are checked for the unknown fields.
+    fn impl_field_wire_decoding(
+        &self,
+        strict: bool,
+    ) -> (
+        proc_macro2::TokenStream,
+        Option<(u32, proc_macro2::TokenStream)>,
+        proc_macro2::TokenStream,
+    ) {
+        if self.attrs.skip || self.attrs.to_pb_only {
+            return (quote! {}, None, self.impl_field_init());
+        }
+        if self.attrs.unknown_fields {
+            return (quote! {}, None, quote! { unknown_fields.clone() });
+        }
+
+        let number = self.attrs.number.unwrap();
+        let var = Ident::new(&format!("pb_{}", self.name.unraw()), Span::call_site());
+        let proto_type = self.proto_type();
+        let ty = self.wire_value_type();
+        let message = if strict {
+            // The nested messages are parsed to check them like `from_pb` does.
+            quote! {{
+                let pb: <#ty as ProtobufConvert>::ProtoStruct = is.read_message()?;
+                check_unknown_fields(&pb)?;
+                <#ty as ProtobufConvert>::from_pb(pb)?
+            }}
+        } else {
+            quote! {{
+                let len = is.read_raw_varint64()?;
+                let old_limit = is.push_limit(len)?;
+                let value = <#ty as ProtobufConvert>::decode(is)?;
+                is.pop_limit(old_limit);
+                value
+            }}
+        };
+        let check_message = quote! {
+            if wire_type != protobuf::wire_format::WireTypeLengthDelimited {
+                return Err(protobuf::rt::unexpected_wire_type(wire_type).into());
+            }
+        };
+
+        if self.repeated_elem_type().is_some() {
+            let declaration = quote! { let mut #var = Vec::new(); };
+            let messages = proto_type == ProtoType::Message;
+            let read = if messages {
+                quote! {
+                    #check_message
+                    #var.push(#message);
+                }
+            } else {
+                proto_type.read_repeated(quote! { #var })
+            };
+            // Messages are decoded into the elements, while the other values are converted
+            // with `from_pb` of the elements.
+            let value = match self.value_conversion() {
+                ValueConversion::Array(len) if messages => {
+                    let err = self.length_error(len, quote! { items.len() });
+                    quote! {
+                        std::convert::TryFrom::try_from(#var).map_err(|items: Vec<_>| #err)?
+                    }
+                }
+                ValueConversion::Set(set) if messages => {
+                    self.collect_set(set, quote! { #var }, quote! { item })
+                }
+                ValueConversion::Array(_) | ValueConversion::Set(_) => {
+                    self.convert_from_pb(quote! { #var }).into_token_stream()
+                }
+                _ if messages => quote! { #var },
+                _ => quote! {
+                    #var
+                        .into_iter()
+                        .map(ProtobufConvert::from_pb)
+                        .collect::<std::result::Result<Vec<_>, _>>()?
+                },
+            };
+            let value = if self.attrs.default_as_none {
+                quote! {
+                    if #var.is_empty() {
+                        None
+                    } else {
+                        Some(#value)
+                    }
+                }
+            } else if let Some(default_value) = &self.default_value {
+                quote! {
+                    if #var.is_empty() {
+                        #default_value
+                    } else {
+                        #value
+                    }
+                }
+            } else if self.optional_inner_type().is_some() {
+                quote! { Some(#value) }
+            } else {
+                value
+            };
+            return (declaration, Some((number, read)), value);
+        }
+
+        if proto_type == ProtoType::Message {
+            let declaration = quote! { let mut #var = None; };
+            let read = quote! {
+                #check_message
+                #var = Some(#message);
+            };
+            let constructor = match self.value_conversion() {
+                ValueConversion::Pointer(constructor) => Some(constructor),
+                _ => None,
+            };
+            // Absent messages are decoded from the default message.
+            let value = quote! {
+                match #var {
+                    Some(value) => value,
+                    None => <#ty as ProtobufConvert>::from_pb(Default::default())?,
+                }
+            };
+            let value = match constructor {
+                Some(constructor) if self.optional_inner_type().is_some() => {
+                    quote! { #var.map(#constructor) }
+                }
+                Some(constructor) => quote! { #constructor(#value) },
+                None => value,
+            };
+            return (declaration, Some((number, read)), value);
+        }
+
+        let declaration = if proto_type == ProtoType::Enum {
+            quote! { let mut #var: <#ty as ProtobufConvert>::ProtoStruct = Default::default(); }
+        } else {
+            quote! { let mut #var = Default::default(); }
+        };
+        let read = proto_type.read(quote! { #var });
+        let convert = |value: proc_macro2::TokenStream| match self.value_conversion() {
+            ValueConversion::Bytes(len) => {
+                let err = self.length_error(len, quote! { bytes.len() });
+                quote! {{
+                    let bytes: Vec<u8> = #value;
+                    std::convert::TryFrom::try_from(bytes.as_slice()).map_err(|_| #err)?
+                }}
+            }
+            ValueConversion::Pointer(constructor) => {
+                quote! { #constructor(ProtobufConvert::from_pb(#value)?) }
+            }
+            _ => quote! { ProtobufConvert::from_pb(#value)? },
+        };
+        let value = if self.attrs.default_as_none {
+            let value = convert(quote! { #var });
+            quote! {
+                if is_default_value(&#var) {
+                    None
+                } else {
+                    Some(#value)
+                }
+            }
+        } else if let Some(default_value) = &self.default_value {
+            let value = convert(quote! { #var });
+            quote! {
+                if is_default_value(&#var) {
+                    #default_value
+                } else {
+                    #value
+                }
+            }
+        } else if self.optional_inner_type().is_some() {
+            let value = convert(quote! { #var });
+            quote! { Some(#value) }
+        } else {
+            convert(quote! { #var })
+        };
+        (declaration, Some((number, read)), value)
+    }
+}
+
+impl ToTokens for ProtobufConvertStruct {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let name = &self.name;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+        let pb_name = &self.attrs.source;
+
+        let from_pb_impl = {
+            let fields = self.fields.iter().map(ParsedField::impl_field_setter);
+            let computed = self
+                .fields
+                .iter()
+                .filter_map(ParsedField::impl_field_compute)
+                .collect::<Vec<_>>();
+            let mutability = if computed.is_empty() {
+                quote! {}
+            } else {
+                quote! { mut }
+            };
+
+            let check = if self.attrs.deny_unknown_fields {
+                quote! { check_unknown_fields(&pb)?; }
+            } else {
+                quote! {}
+            };
+            let take = self.impl_take_pb();
+
+            quote! {
+                #check
+                #take
+                let #mutability inner = Self {
+                    #(#fields)*
+                };
+                #(#computed)*
+                Ok(inner)
+            }
+        };
+
+        let to_pb_impl = {
+            let fields = self.fields.iter().map(ParsedField::impl_field_getter);
+
+            quote! {
+                let mut msg = Self::ProtoStruct::default();
+                #(#fields)*
+                msg
+            }
+        };
+
+        let into_pb_impl = if self.attrs.into_pb {
+            // Computed fields borrow `self`, so they are set before the other fields are moved.
+            let (computed, fields): (Vec<_>, Vec<_>) = self
+                .fields
+                .iter()
+                .partition(|field| field.compute.is_some());
+            let fields = computed
+                .into_iter()
+                .chain(fields)
+                .map(ParsedField::impl_field_into_pb);
+
+            quote! {
+                fn into_pb(self) -> Self::ProtoStruct {
+                    let mut msg = Self::ProtoStruct::default();
+                    #(#fields)*
+                    msg
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        let reuse_buffers_impl = if self.attrs.reuse_buffers {
+            let to_pb_fields = self.fields.iter().map(ParsedField::impl_field_to_pb_into);
+            let from_pb_fields = self.fields.iter().map(ParsedField::impl_field_from_pb_into);
+            let computed = self.fields.iter().filter_map(|field| {
+                let ident = &field.name;
+                field.compute.as_ref().map(|compute| {
+                    quote! { self.#ident = (#compute)(self); }
+                })
+            });
+            let check = if self.attrs.deny_unknown_fields {
+                quote! { check_unknown_fields(&pb)?; }
+            } else {
+                quote! {}
+            };
+            let take = self.impl_take_pb();
+
+            quote! {
+                fn to_pb_into(&self, msg: &mut Self::ProtoStruct) {
+                    protobuf::Clear::clear(msg);
+                    #(#to_pb_fields)*
+                }
+
+                fn from_pb_into(
+                    &mut self,
+                    pb: Self::ProtoStruct,
+                ) -> std::result::Result<(), anyhow::Error> {
+                    #check
+                    #take
+                    #(#from_pb_fields)*
+                    #(#computed)*
+                    Ok(())
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        let (wire_impl, wire_sizes_impl) = if self.attrs.wire {
+            let mut encodings = self
+                .fields
+                .iter()
+                .filter_map(ParsedField::impl_field_wire_encoding)
+                .collect::<Vec<_>>();
+            // rust-protobuf writes the fields in the order of their declaration in the proto
+            // file, which is checked to follow the field numbers by `check_wire_order`.
+            encodings.sort_by_key(|(number, _, _)| *number);
+            let (mut lens, mut writes): (Vec<_>, Vec<_>) = encodings
+                .into_iter()
+                .map(|(_, len, write)| (len, write))
+                .unzip();
+            // Unknown fields are written after the known ones.
+            for field in self
+                .fields
+                .iter()
+                .filter(|field| field.attrs.unknown_fields)
+            {
+                let ident = &field.name;
+                lens.push(quote! { protobuf::rt::unknown_fields_size(&self.#ident) });
+                writes.push(quote! { os.write_unknown_fields(&self.#ident)?; });
+            }
+            let encoded_len = if lens.is_empty() {
+                quote! {
+                    let _ = sizes;
+                    0
+                }
+            } else {
+                quote! {
+                    let mut len = 0;
+                    #( len += #lens; )*
+                    len
+                }
+            };
+
+            let strict = self.attrs.deny_unknown_fields;
+            let mut declarations = Vec::new();
+            let mut numbers = Vec::new();
+            let mut reads = Vec::new();
+            let mut fields = Vec::new();
+            for field in &self.fields {
+                let (declaration, read, value) = field.impl_field_wire_decoding(strict);
+                declarations.push(declaration);
+                if let Some((number, read)) = read {
+                    numbers.push(number);
+                    reads.push(read);
+                }
+                let ident = &field.name;
+                fields.push(quote! { #ident: #value, });
+            }
+            let computed = self
+                .fields
+                .iter()
+                .filter_map(ParsedField::impl_field_compute)
+                .collect::<Vec<_>>();
+            let mutability = if computed.is_empty() {
+                quote! {}
+            } else {
+                quote! { mut }
+            };
+            let depth_guard = if self.decodes_messages() {
+                quote! { let _depth = DecodeDepthGuard::enter()?; }
+            } else {
+                quote! {}
+            };
+            let check = if strict {
+                quote! {
+                    let mut tags = unknown_fields
+                        .iter()
+                        .map(|(tag, _)| tag)
+                        .collect::<Vec<_>>();
+                    if !tags.is_empty() {
+                        tags.sort_unstable();
+                        return Err(anyhow::anyhow!(
+                            "Unknown fields with tags {:?} in message `{}`",
+                            tags,
+                            <Self::ProtoStruct as protobuf::Message>::descriptor_static()
+                                .full_name()
+                        ));
+                    }
+                }
+            } else {
+                quote! {}
+            };
+
+            let wire_impl = quote! {
+                fn encoded_len(&self) -> u32 {
+                    self.pb_convert_compute_sizes(&mut Vec::new())
+                }
+
+                fn decode(
+                    is: &mut protobuf::CodedInputStream<'_>,
+                ) -> std::result::Result<Self, anyhow::Error> {
+                    check_wire_schema();
+                    #depth_guard
+                    let mut unknown_fields = protobuf::UnknownFields::new();
+                    #( #declarations )*
+                    while !is.eof()? {
+                        let (field_number, wire_type) = is.read_tag_unpack()?;
+                        match field_number {
+                            #(
+                                #numbers => {
+                                    #reads
+                                }
+                            )*
+                            // Fields of the message which are not mapped to the struct.
+                            _ if <Self::ProtoStruct as protobuf::Message>::descriptor_static()
+                                .get_field_by_number(field_number)
+                                .is_some() =>
+                            {
+                                is.skip_field(wire_type)?;
+                            }
+                            _ => protobuf::rt::read_unknown_or_skip_group(
+                                field_number,
+                                wire_type,
+                                is,
+                                &mut unknown_fields,
+                            )?,
+                        }
+                    }
+                    #check
+
+                    let #mutability inner = Self {
+                        #( #fields )*
+                    };
+                    #( #computed )*
+                    Ok(inner)
+                }
+
+                fn encode(
+                    &self,
+                    os: &mut protobuf::CodedOutputStream<'_>,
+                ) -> protobuf::ProtobufResult<()> {
+                    let mut sizes = Vec::new();
+                    self.pb_convert_compute_sizes(&mut sizes);
+                    self.pb_convert_encode_with_sizes(os, &mut sizes.iter())
+                }
+            };
+            let wire_sizes_impl = quote! {
+                impl #impl_generics #name #ty_generics #where_clause {
+                    /// Length of the wire format, recording the lengths of the nested messages
+                    /// in the order they are written.
+                    #[doc(hidden)]
+                    #[allow(clippy::ptr_arg)]
+                    pub(crate) fn pb_convert_compute_sizes(&self, sizes: &mut Vec<u32>) -> u32 {
+                        check_wire_schema();
+                        #encoded_len
+                    }
 
-        let setter = match (self.skip, &self.with) {
-            // Usual setter.
-            (false, None) => quote! { ProtobufConvert::from_pb(pb.#pb_getter().to_owned())? },
-            // Setter with the overridden Protobuf conversion.
-            (false, Some(with)) => quote! { #with::from_pb(pb.#pb_getter().to_owned())? },
-            // Default setter for the skipped fields.
-            (true, _) => quote! { Default::default() },
+                    /// Writes the wire format using the lengths recorded by
+                    /// `pb_convert_compute_sizes`.
+                    #[doc(hidden)]
+                    pub(crate) fn pb_convert_encode_with_sizes(
+                        &self,
+                        os: &mut protobuf::CodedOutputStream<'_>,
+                        sizes: &mut std::slice::Iter<'_, u32>,
+                    ) -> protobuf::ProtobufResult<()> {
+                        let _ = &sizes;
+                        #( #writes )*
+                        Ok(())
+                    }
+                }
+            };
+            (wire_impl, wire_sizes_impl)
+        } else {
+            (quote! {}, quote! {})
         };
 
-        quote! { #ident: #setter, }
-    }
+        let to_pb_masked_impl = if self.attrs.field_mask {
+            let (declarations, (matching, setters)): (Vec<_>, (Vec<_>, Vec<_>)) = self
+                .fields
+                .iter()
+                .filter_map(ParsedField::impl_field_masked_getter)
+                .map(|(declaration, matching, setter)| (declaration, (matching, setter)))
+                .unzip();
 
-    fn impl_field_getter(&self, ident: &Ident) -> impl ToTokens {
-        let pb_setter = Ident::new(&format!("set_{}", ident), Span::call_site());
+            quote! {
+                fn to_pb_masked(
+                    &self,
+                    mask: &protobuf::well_known_types::FieldMask,
+                ) -> std::result::Result<Self::ProtoStruct, anyhow::Error> {
+                    if mask.get_paths().is_empty() {
+                        return Ok(self.to_pb());
+                    }
 
-        match (self.skip, &self.with) {
-            // Usual getter.
-            (false, None) => quote! {
-                msg.#pb_setter(ProtobufConvert::to_pb(&self.#ident).into());
-            },
-            // Getter with the overridden Protobuf conversion.
-            (false, Some(with)) => quote! {
-                msg.#pb_setter(#with::to_pb(&self.#ident).into());
-            },
-            // Skipped getter does nothing.
-            (true, _) => quote! {},
-        }
-    }
-}
+                    #( #declarations )*
+                    for path in mask.get_paths() {
+                        let mut matched = false;
+                        #( #matching )*
+                        if !matched {
+                            return Err(UnknownMaskPath {
+                                path: path.to_owned(),
+                            }
+                            .into());
+                        }
+                    }
 
-impl ToTokens for ProtobufConvertStruct {
-    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
-        let name = &self.name;
-        let pb_name = &self.attrs.source;
+                    let mut msg = Self::ProtoStruct::default();
+                    #( #setters )*
+                    Ok(msg)
+                }
+            }
+        } else {
+            quote! {}
+        };
 
-        let from_pb_impl = {
-            let fields = self
+        // Checks the paths selecting the subfields of the absent optional values, which
+        // are not passed to `to_pb_masked` of the field type.
+        let mask_check_impl = if self.attrs.field_mask {
+            let (declarations, (matching, checks)): (Vec<_>, (Vec<_>, Vec<_>)) = self
                 .fields
                 .iter()
-                .map(|(ident, attrs)| attrs.impl_field_setter(ident));
+                .filter_map(ParsedField::impl_field_mask_check)
+                .map(|(declaration, matching, check)| (declaration, (matching, check)))
+                .unzip();
 
             quote! {
-                let inner = Self {
-                    #(#fields)*
-                };
-                Ok(inner)
+                impl #impl_generics #name #ty_generics #where_clause {
+                    #[doc(hidden)]
+                    #[allow(dead_code)]
+                    pub(crate) fn check_mask_paths(
+                        paths: &[String],
+                    ) -> std::result::Result<(), anyhow::Error> {
+                        #( #declarations )*
+                        for path in paths {
+                            let mut matched = false;
+                            #( #matching )*
+                            if !matched {
+                                return Err(UnknownMaskPath {
+                                    path: path.to_owned(),
+                                }
+                                .into());
+                            }
+                        }
+                        #( #checks )*
+                        Ok(())
+                    }
+                }
             }
+        } else {
+            quote! {}
         };
 
-        let to_pb_impl = {
-            let fields = self
-                .fields
-                .iter()
-                .map(|(ident, attrs)| attrs.impl_field_getter(ident));
+        let merge_from_pb_impl = if self.attrs.merge {
+            // The check covers the nested messages merged with their own `merge_from_pb`.
+            let check = if self.attrs.deny_unknown_fields {
+                quote! { check_unknown_fields(&pb)?; }
+            } else {
+                quote! {}
+            };
+            let take = self.impl_take_pb();
+            let mut declarations = Vec::new();
+            let mut matching = Vec::new();
+            let mut masked_merges = Vec::new();
+            let mut present_merges = Vec::new();
+            for (declaration, matches, masked_merge, present_merge) in
+                self.fields.iter().filter_map(ParsedField::impl_field_merge)
+            {
+                declarations.push(declaration);
+                matching.push(matches);
+                masked_merges.push(masked_merge);
+                present_merges.push(present_merge);
+            }
+            let computed = self.fields.iter().filter_map(|field| {
+                let ident = &field.name;
+                field.compute.as_ref().map(|compute| {
+                    quote! { self.#ident = (#compute)(self); }
+                })
+            });
 
             quote! {
-                let mut msg = Self::ProtoStruct::default();
-                #(#fields)*
-                msg
+                fn merge_from_pb(
+                    &mut self,
+                    pb: Self::ProtoStruct,
+                    mask: Option<&protobuf::well_known_types::FieldMask>,
+                ) -> std::result::Result<(), anyhow::Error> {
+                    #check
+                    #take
+                    match mask {
+                        Some(mask) if !mask.get_paths().is_empty() => {
+                            #( #declarations )*
+                            for path in mask.get_paths() {
+                                let mut matched = false;
+                                #( #matching )*
+                                if !matched {
+                                    return Err(UnknownMaskPath {
+                                        path: path.to_owned(),
+                                    }
+                                    .into());
+                                }
+                            }
+                            #( #masked_merges )*
+                        }
+                        _ => {
+                            #( #present_merges )*
+                        }
+                    }
+                    #( #computed )*
+                    Ok(())
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        let registration = if self.attrs.register {
+            let full_name = match &self.attrs.full_name {
+                Some(full_name) => quote! { #full_name },
+                None if self.attrs.backend == Backend::Protobuf => quote! {
+                    <#pb_name as protobuf::Message>::descriptor_static().full_name()
+                },
+                // Descriptors of rust-protobuf 3.x are not static, so the name is copied once.
+                None => quote! {{
+                    static FULL_NAME: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+                    FULL_NAME.get_or_init(|| {
+                        <#pb_name as protobuf::MessageFull>::descriptor()
+                            .full_name()
+                            .to_owned()
+                    })
+                }},
+            };
+            quote! {
+                impl #impl_generics #name #ty_generics #where_clause {
+                    /// Full name of the protobuf message used in the `google.protobuf.Any`
+                    /// type URLs.
+                    pub fn proto_full_name() -> &'static str {
+                        #full_name
+                    }
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        let mut helpers = if self.fields.iter().any(ParsedField::checks_default_value) {
+            quote! {
+                fn is_default_value<T: Default + PartialEq>(value: &T) -> bool {
+                    *value == T::default()
+                }
             }
+        } else {
+            quote! {}
         };
+        if self.attrs.accessors() == Accessors::Fields
+            || self.fields.iter().any(ParsedField::reads_moved_value)
+        {
+            helpers.extend(self.attrs.backend.field_helpers());
+        }
+        if self.attrs.deny_unknown_fields {
+            helpers.extend(quote! {
+                /// Checks that the message and its nested messages have no unknown fields.
+                fn check_unknown_fields(
+                    pb: &dyn protobuf::Message,
+                ) -> std::result::Result<(), anyhow::Error> {
+                    use protobuf::reflect::{ProtobufValue, ReflectFieldRef, ReflectValueRef};
+
+                    let mut tags = pb
+                        .get_unknown_fields()
+                        .iter()
+                        .map(|(tag, _)| tag)
+                        .collect::<Vec<_>>();
+                    if !tags.is_empty() {
+                        tags.sort_unstable();
+                        return Err(anyhow::anyhow!(
+                            "Unknown fields with tags {:?} in message `{}`",
+                            tags,
+                            pb.descriptor().full_name()
+                        ));
+                    }
+
+                    for field in pb.descriptor().fields() {
+                        let values: Vec<&dyn ProtobufValue> = match field.get_reflect(pb) {
+                            ReflectFieldRef::Optional(value) => {
+                                if let Some(ReflectValueRef::Message(message)) = value {
+                                    check_unknown_fields(message)?;
+                                }
+                                continue;
+                            }
+                            ReflectFieldRef::Repeated(items) => items.reflect_iter().collect(),
+                            ReflectFieldRef::Map(map) => {
+                                map.reflect_iter().map(|(_, value)| value).collect()
+                            }
+                        };
+                        for value in values {
+                            let value = ProtobufValue::as_ref(value);
+                            if let ReflectValueRef::Message(message) = value {
+                                check_unknown_fields(message)?;
+                            }
+                        }
+                    }
+                    Ok(())
+                }
+            });
+        }
+        if self.attrs.wire {
+            let pb_name = &self.attrs.source;
+            let schema_checks = self
+                .fields
+                .iter()
+                .filter_map(ParsedField::impl_field_schema_check);
+            let mut written = self
+                .fields
+                .iter()
+                .filter(|field| {
+                    !field.attrs.skip && !field.attrs.from_pb_only && !field.attrs.unknown_fields
+                })
+                .filter_map(|field| Some((field.attrs.number?, field.pb_field().to_string())))
+                .collect::<Vec<_>>();
+            written.sort_by_key(|(number, _)| *number);
+            let written = written.into_iter().map(|(_, name)| name);
+            helpers.extend(quote! {
+                /// Checks the numbers and the types of the fields declared for the wire format
+                /// against the message descriptor once, in debug builds only.
+                fn check_wire_schema() {
+                    if cfg!(debug_assertions) {
+                        static CHECK: std::sync::Once = std::sync::Once::new();
+                        CHECK.call_once(|| {
+                            let descriptor =
+                                <#pb_name as protobuf::Message>::descriptor_static();
+                            #( #schema_checks )*
+                            check_wire_order(descriptor, &[ #( #written ),* ]);
+                        });
+                    }
+                }
+
+                #[allow(dead_code)]
+                fn check_wire_field(
+                    descriptor: &protobuf::reflect::MessageDescriptor,
+                    name: &str,
+                    number: u32,
+                    field_type: protobuf::descriptor::FieldDescriptorProto_Type,
+                    repeated: bool,
+                ) {
+                    let field = descriptor.get_field_by_name(name).unwrap_or_else(|| {
+                        panic!(
+                            "Field `{}` is not declared in message `{}`",
+                            name,
+                            descriptor.full_name()
+                        )
+                    });
+                    let proto = field.proto();
+                    assert!(
+                        proto.get_number() as u32 == number,
+                        "Field `{}` of message `{}` has number {}, not {}",
+                        name,
+                        descriptor.full_name(),
+                        proto.get_number(),
+                        number
+                    );
+                    assert!(
+                        proto.get_field_type() == field_type && field.is_repeated() == repeated,
+                        "Field `{}` of message `{}` has type {}{:?}, not {}{:?}",
+                        name,
+                        descriptor.full_name(),
+                        if field.is_repeated() { "repeated " } else { "" },
+                        proto.get_field_type(),
+                        if repeated { "repeated " } else { "" },
+                        field_type
+                    );
+                }
+
+                /// rust-protobuf 2.x writes the repeated scalar fields packed only if they
+                /// are declared with `[packed = true]`, including in proto3.
+                #[allow(dead_code)]
+                fn check_wire_packed(
+                    descriptor: &protobuf::reflect::MessageDescriptor,
+                    name: &str,
+                    packed: bool,
+                ) {
+                    let field = descriptor.get_field_by_name(name).unwrap();
+                    let declared = field.proto().get_options().get_packed();
+                    assert!(
+                        declared == packed,
+                        "Field `{}` of message `{}` is {}declared with `[packed = true]`, \
+                         but it is {}marked with `packed`",
+                        name,
+                        descriptor.full_name(),
+                        if declared { "" } else { "not " },
+                        if packed { "" } else { "not " }
+                    );
+                }
+
+                /// rust-protobuf writes the fields in the order of their declaration
+                /// in the proto file, while `encode` writes them in the order of their
+                /// numbers, so both orders must be the same.
+                fn check_wire_order(
+                    descriptor: &protobuf::reflect::MessageDescriptor,
+                    names: &[&str],
+                ) {
+                    let position = |name: &str| {
+                        descriptor
+                            .fields()
+                            .iter()
+                            .position(|field| field.name() == name)
+                            .unwrap()
+                    };
+                    for pair in names.windows(2) {
+                        assert!(
+                            position(pair[0]) < position(pair[1]),
+                            "Fields `{}` and `{}` of message `{}` are not declared in the order \
+                             of their numbers, which is not supported by `wire`",
+                            pair[0],
+                            pair[1],
+                            descriptor.full_name()
+                        );
+                    }
+                }
+            });
+        }
+        if self.attrs.wire && self.encodes_messages() {
+            helpers.extend(quote! {
+                /// Writes the nested messages of the types without `wire` through `encoded_len`
+                /// and `encode`. The inherent methods of the types deriving `wire` take
+                /// precedence over these ones and reuse the lengths of their nested messages.
+                trait WireSizes {
+                    fn pb_convert_compute_sizes(&self, sizes: &mut Vec<u32>) -> u32;
+
+                    fn pb_convert_encode_with_sizes(
+                        &self,
+                        os: &mut protobuf::CodedOutputStream<'_>,
+                        sizes: &mut std::slice::Iter<'_, u32>,
+                    ) -> protobuf::ProtobufResult<()>;
+                }
+
+                impl<T: ProtobufConvert> WireSizes for T
+                where
+                    T::ProtoStruct: protobuf::Message,
+                {
+                    fn pb_convert_compute_sizes(&self, sizes: &mut Vec<u32>) -> u32 {
+                        let _ = sizes;
+                        ProtobufConvert::encoded_len(self)
+                    }
+
+                    fn pb_convert_encode_with_sizes(
+                        &self,
+                        os: &mut protobuf::CodedOutputStream<'_>,
+                        sizes: &mut std::slice::Iter<'_, u32>,
+                    ) -> protobuf::ProtobufResult<()> {
+                        let _ = sizes;
+                        ProtobufConvert::encode(self, os)
+                    }
+                }
+            });
+        }
+        if self.attrs.wire && self.decodes_messages() {
+            helpers.extend(quote! {
+                thread_local! {
+                    static DECODE_DEPTH: std::cell::Cell<u32> = std::cell::Cell::new(0);
+                }
+
+                /// Limits the depth of the nested messages decoded by `decode`, like the default
+                /// recursion limit of `CodedInputStream`.
+                struct DecodeDepthGuard;
+
+                impl DecodeDepthGuard {
+                    fn enter() -> std::result::Result<Self, anyhow::Error> {
+                        // The outermost message is not counted, as in `CodedInputStream`.
+                        let depth = DECODE_DEPTH.with(std::cell::Cell::get);
+                        if depth > 100 {
+                            return Err(protobuf::ProtobufError::WireError(
+                                protobuf::error::WireError::OverRecursionLimit,
+                            )
+                            .into());
+                        }
+                        DECODE_DEPTH.with(|cell| cell.set(depth + 1));
+                        Ok(DecodeDepthGuard)
+                    }
+                }
+
+                impl Drop for DecodeDepthGuard {
+                    fn drop(&mut self) {
+                        DECODE_DEPTH.with(|cell| cell.set(cell.get() - 1));
+                    }
+                }
+            });
+        }
+        if self.attrs.field_mask || self.attrs.merge {
+            helpers.extend(quote! {
+                /// Returns an empty string if the field mask path selects the whole field
+                /// and the rest of the path if it selects the subfields of the field.
+                fn field_mask_tail<'a>(path: &'a str, field_path: &str) -> Option<&'a str> {
+                    if let Some(tail) = path.strip_prefix(field_path) {
+                        if tail.is_empty() {
+                            return Some("");
+                        }
+                        return tail.strip_prefix('.');
+                    }
+                    match field_path.strip_prefix(path) {
+                        Some(tail) if tail.starts_with('.') => Some(""),
+                        _ => None,
+                    }
+                }
+
+                /// Checks the field mask paths against the types that do not support
+                /// field masks, which reject any path like the default `to_pb_masked`.
+                /// The inherent method of the types deriving `field_mask` takes
+                /// precedence over this one.
+                #[allow(dead_code)]
+                trait CheckMaskPaths {
+                    fn check_mask_paths(
+                        paths: &[String],
+                    ) -> std::result::Result<(), anyhow::Error> {
+                        match paths.first() {
+                            Some(path) => Err(UnknownMaskPath {
+                                path: path.to_owned(),
+                            }
+                            .into()),
+                            None => Ok(()),
+                        }
+                    }
+                }
+
+                impl<T: ?Sized> CheckMaskPaths for T {}
+
+                /// Prefixes the path reported by the unknown field mask path error of
+                /// the field with the path of the field, so that the error names the path
+                /// as it was given in the mask.
+                #[allow(dead_code)]
+                fn nested_mask_error(error: anyhow::Error, field_path: &str) -> anyhow::Error {
+                    match error.downcast::<UnknownMaskPath>() {
+                        Ok(UnknownMaskPath { path }) => UnknownMaskPath {
+                            path: format!("{}.{}", field_path, path),
+                        }
+                        .into(),
+                        Err(error) => error,
+                    }
+                }
+            });
+        }
+        if self.attrs.reuse_buffers {
+            helpers.extend(quote! {
+                /// Appends the default element to the repeated scalar fields, which are
+                /// stored in `Vec` rather than in `RepeatedField`. The inherent method
+                /// of `RepeatedField` reuses the cleared elements instead.
+                #[allow(dead_code)]
+                trait PushDefault<T> {
+                    fn push_default(&mut self) -> &mut T;
+                }
+
+                impl<T: Default> PushDefault<T> for Vec<T> {
+                    fn push_default(&mut self) -> &mut T {
+                        self.push(T::default());
+                        self.last_mut().unwrap()
+                    }
+                }
+            });
+        }
+        if self.attrs.merge {
+            helpers.extend(quote! {
+                /// Checks whether the value read from a protobuf field differs from the default
+                /// value, which is how proto3 encodes the presence of the scalar fields.
+                /// Called as a method, so messages are compared by value and strings,
+                /// bytes and repeated fields by reference.
+                trait FieldPresence {
+                    fn is_set(&self) -> bool;
+                }
+
+                impl<T: Default + PartialEq> FieldPresence for T {
+                    fn is_set(&self) -> bool {
+                        *self != T::default()
+                    }
+                }
+            });
+        }
 
         let expanded = quote! {
-            impl ProtobufConvert for #name {
+            #helpers
+            #registration
+            #wire_sizes_impl
+            #mask_check_impl
+
+            impl #impl_generics ProtobufConvert for #name #ty_generics #where_clause {
                 type ProtoStruct = #pb_name;
 
                 fn from_pb(pb: Self::ProtoStruct) -> std::result::Result<Self, anyhow::Error> {
@@ -215,6 +3028,12 @@ impl ToTokens for ProtobufConvertStruct {
                 fn to_pb(&self) -> Self::ProtoStruct {
                     #to_pb_impl
                 }
+
+                #into_pb_impl
+                #reuse_buffers_impl
+                #wire_impl
+                #to_pb_masked_impl
+                #merge_from_pb_impl
             }
         };
         tokens.extend(expanded);
@@ -259,6 +3078,7 @@ impl TryFrom<&Variant> for ParsedVariant {
 #[derive(Debug)]
 struct ProtobufConvertEnum {
     name: Ident,
+    generics: Generics,
     variants: Vec<ParsedVariant>,
     attrs: ProtobufConvertEnumAttrs,
 }
@@ -272,71 +3092,288 @@ pub struct Rename {
 impl ProtobufConvertEnum {
     fn from_derive_input(
         name: Ident,
+        generics: Generics,
         data: &DataEnum,
         attrs: &[Attribute],
     ) -> Result<Self, darling::Error> {
         let attrs = ProtobufConvertEnumAttrs::try_from(attrs)?;
+        if attrs.any && !attrs.backend.supports("any") {
+            return Err(attrs.backend.unsupported("any"));
+        }
+        // The registry of the variants is a static shared by all instantiations.
+        if attrs.any && !generics.params.is_empty() {
+            return Err(darling::Error::custom(
+                "`any` cannot be used with generic enums",
+            ));
+        }
         let variants = data
             .variants
             .iter()
             .map(ParsedVariant::try_from)
             .collect::<Result<Vec<_>, _>>()?;
 
+        let converted = variants
+            .iter()
+            .map(|variant| variant.field_name.to_token_stream())
+            .collect::<Vec<_>>();
         Ok(Self {
             name,
+            generics: add_trait_bounds(generics, &converted),
             attrs,
             variants,
         })
     }
 
+    /// Implements the conversion into `google.protobuf.Any`, selecting the variant
+    /// by the full name of the packed message.
+    fn impl_protobuf_convert_any(&self) -> impl ToTokens {
+        let name = &self.name;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+        let uses_fields = self.attrs.backend == Backend::Protobuf3;
+        let pb_name = match &self.attrs.source {
+            Some(source) => quote! { #source },
+            None if uses_fields => quote! { protobuf::well_known_types::any::Any },
+            None => quote! { protobuf::well_known_types::Any },
+        };
+        let (type_url, value) = if uses_fields {
+            (quote! { pb.type_url }, quote! { pb.value })
+        } else {
+            (quote! { pb.get_type_url() }, quote! { pb.get_value() })
+        };
+
+        let registry = {
+            let entries = self.variants.iter().map(|variant| {
+                let variant_name = &variant.name;
+                let field_name = &variant.field_name;
+
+                quote! {
+                    (
+                        <#field_name>::proto_full_name(),
+                        (|bytes: &[u8]| {
+                            let mut inner = <#field_name as ProtobufConvert>::ProtoStruct::new();
+                            inner.merge_from_bytes(bytes)?;
+                            <#field_name as ProtobufConvert>::from_pb(inner)
+                                .map(#name::#variant_name)
+                        }) as AnyDecoder,
+                    )
+                }
+            });
+
+            quote! {
+                type AnyDecoder = fn(&[u8]) -> std::result::Result<#name, anyhow::Error>;
+
+                /// Registry of the variants keyed by the full names of their messages. The names
+                /// registered by several variants are ambiguous and are mapped to `None`.
+                fn any_registry(
+                ) -> &'static std::collections::HashMap<&'static str, Option<AnyDecoder>> {
+                    static REGISTRY: std::sync::OnceLock<
+                        std::collections::HashMap<&'static str, Option<AnyDecoder>>,
+                    > = std::sync::OnceLock::new();
+
+                    REGISTRY.get_or_init(|| {
+                        let entries: Vec<(&'static str, AnyDecoder)> = vec![ #( #entries ),* ];
+                        let mut registry = std::collections::HashMap::new();
+                        for (full_name, decoder) in entries {
+                            registry
+                                .entry(full_name)
+                                .and_modify(|entry| *entry = None)
+                                .or_insert(Some(decoder));
+                        }
+                        registry
+                    })
+                }
+
+                /// Encodes the packed message. Unlike `write_to_bytes`, the required fields
+                /// of proto2 messages are not checked, as when packing partial messages
+                /// in the other implementations, and writing into a vector cannot fail.
+                fn encode_any_value<M: protobuf::Message>(msg: &M) -> Vec<u8> {
+                    let mut bytes = Vec::with_capacity(msg.compute_size() as usize);
+                    let mut os = protobuf::CodedOutputStream::vec(&mut bytes);
+                    let _ = msg
+                        .write_to_with_cached_sizes(&mut os)
+                        .and_then(|()| os.flush());
+                    drop(os);
+                    bytes
+                }
+            }
+        };
+        let from_pb_impl = quote! {
+            let full_name = #type_url.rsplit('/').next().unwrap_or_default();
+            match any_registry().get(full_name) {
+                Some(Some(decode)) => decode(&#value),
+                Some(None) => Err(anyhow::anyhow!(
+                    "Type `{}` is registered by several variants of `{}`",
+                    full_name,
+                    stringify!(#name)
+                )),
+                None => Err(anyhow::anyhow!("Unknown type URL `{}`", #type_url)),
+            }
+        };
+        // `to_pb` and the consuming `into_pb` differ only by the conversion of the variants.
+        let impl_to_pb = |convert: Ident| {
+            let match_arms = self.variants.iter().map(|variant| {
+                let variant_name = &variant.name;
+                let field_name = &variant.field_name;
+
+                if uses_fields {
+                    return quote! {
+                        #name::#variant_name(msg) => {
+                            inner.type_url = format!(
+                                "{}{}",
+                                ANY_TYPE_URL_PREFIX,
+                                <#field_name>::proto_full_name()
+                            );
+                            inner.value = encode_any_value(&msg.#convert());
+                        }
+                    };
+                }
+                quote! {
+                    #name::#variant_name(msg) => {
+                        inner.set_type_url(format!(
+                            "{}{}",
+                            ANY_TYPE_URL_PREFIX,
+                            <#field_name>::proto_full_name()
+                        ));
+                        inner.set_value(encode_any_value(&msg.#convert()));
+                    }
+                }
+            });
+
+            quote! {
+                let mut inner = Self::ProtoStruct::new();
+                match self {
+                    #( #match_arms )*
+                }
+                inner
+            }
+        };
+
+        let to_pb_impl = impl_to_pb(Ident::new("to_pb", Span::call_site()));
+        let into_pb_impl = if self.attrs.into_pb {
+            let into_pb_impl = impl_to_pb(Ident::new("into_pb", Span::call_site()));
+            quote! {
+                fn into_pb(self) -> Self::ProtoStruct {
+                    #into_pb_impl
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        quote! {
+            const ANY_TYPE_URL_PREFIX: &str = "type.googleapis.com/";
+
+            #registry
+
+            impl #impl_generics ProtobufConvert for #name #ty_generics #where_clause {
+                type ProtoStruct = #pb_name;
+
+                fn from_pb(pb: Self::ProtoStruct) -> std::result::Result<Self, anyhow::Error> {
+                    #from_pb_impl
+                }
+
+                fn to_pb(&self) -> Self::ProtoStruct {
+                    #to_pb_impl
+                }
+
+                #into_pb_impl
+            }
+        }
+    }
+
     fn impl_protobuf_convert(&self) -> impl ToTokens {
+        let backend = self.attrs.backend;
+        let uses_fields = backend.default_accessors() == Accessors::Fields;
         let pb_oneof_enum = {
             let mut pb = self.attrs.source.clone().unwrap();
             let oneof = pb.segments.pop().unwrap().value().ident.clone();
-            let oneof_enum = Ident::new(
-                &format!("{}_oneof_{}", oneof, &self.attrs.oneof_field),
-                Span::call_site(),
-            );
-            quote! { #pb #oneof_enum }
+            if backend == Backend::QuickProtobuf {
+                let module = Ident::new(&format!("mod_{}", oneof), Span::call_site());
+                let oneof_enum = Ident::new(
+                    &format!("OneOf{}", &self.attrs.oneof_field),
+                    Span::call_site(),
+                );
+                quote! { #pb #module::#oneof_enum }
+            } else if uses_fields {
+                // Oneof enums are placed into the module named after the message.
+                let module = Ident::new(&oneof.to_string().to_snake_case(), Span::call_site());
+                let oneof_enum = Ident::new(
+                    &self.attrs.oneof_field.to_string().to_camel_case(),
+                    Span::call_site(),
+                );
+                quote! { #pb #module::#oneof_enum }
+            } else {
+                let oneof_enum = Ident::new(
+                    &format!("{}_oneof_{}", oneof, &self.attrs.oneof_field),
+                    Span::call_site(),
+                );
+                quote! { #pb #oneof_enum }
+            }
         };
         let name = &self.name;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
         let pb_name = &self.attrs.source;
         let oneof = &self.attrs.oneof_field;
+        // Absent oneofs of quick-protobuf are represented by the `None` variant
+        // of the oneof enum itself.
+        let wrap_oneof = |value: proc_macro2::TokenStream| {
+            if backend == Backend::QuickProtobuf {
+                value
+            } else {
+                quote! { Some(#value) }
+            }
+        };
 
         let from_pb_impl = {
             let match_arms = self.variants.iter().map(|variant| {
-                let variant_name = self.get_variant_name(variant);
-                let pb_variant = Ident::new(variant_name.as_ref(), Span::call_site());
+                let pb_variant = self.get_pb_variant(variant);
                 let variant_name = &variant.name;
                 let field_name = &variant.field_name;
+                let pattern = wrap_oneof(quote! { #pb_oneof_enum::#pb_variant(pb) });
 
                 quote! {
-                    Some(#pb_oneof_enum::#pb_variant(pb)) => {
+                    #pattern => {
                         #field_name::from_pb(pb).map(#name::#variant_name)
                     }
                 }
             });
 
+            // Oneof enums of rust-protobuf 3.x are non-exhaustive.
+            let absent = match backend {
+                Backend::Protobuf3 => quote! { _ },
+                Backend::QuickProtobuf => quote! { #pb_oneof_enum::None },
+                _ => quote! { None },
+            };
             quote! {
                 match pb.#oneof {
                     #( #match_arms )*
-                    None => Err(anyhow::anyhow!("Failed to decode #name from protobuf"))
+                    #absent => Err(anyhow::anyhow!("Failed to decode #name from protobuf"))
                 }
             }
         };
-        let to_pb_impl = {
+        // `to_pb` and the consuming `into_pb` differ only by the conversion of the variants.
+        let impl_to_pb = |convert: Ident| {
             let match_arms = self.variants.iter().map(|variant| {
-                let pb_variant = self.get_variant_name(variant);
                 let variant_name = &variant.name;
+                if uses_fields {
+                    let pb_variant = self.get_pb_variant(variant);
+                    let value = wrap_oneof(quote! { #pb_oneof_enum::#pb_variant(msg.#convert()) });
+                    return quote! {
+                        #name::#variant_name(msg) => {
+                            inner.#oneof = #value;
+                        }
+                    };
+                }
 
+                let pb_variant = self.get_variant_name(variant);
                 let setter = Ident::new(&format!("set_{}", pb_variant), Span::call_site());
                 quote! {
-                    #name::#variant_name(msg) => inner.#setter(msg.to_pb()),
+                    #name::#variant_name(msg) => inner.#setter(msg.#convert()),
                 }
             });
 
             quote! {
-                let mut inner = Self::ProtoStruct::new();
+                let mut inner = Self::ProtoStruct::default();
                 match self {
                     #( #match_arms )*
                 }
@@ -344,8 +3381,20 @@ impl ProtobufConvertEnum {
             }
         };
 
+        let to_pb_impl = impl_to_pb(Ident::new("to_pb", Span::call_site()));
+        let into_pb_impl = if self.attrs.into_pb {
+            let into_pb_impl = impl_to_pb(Ident::new("into_pb", Span::call_site()));
+            quote! {
+                fn into_pb(self) -> Self::ProtoStruct {
+                    #into_pb_impl
+                }
+            }
+        } else {
+            quote! {}
+        };
+
         quote! {
-            impl ProtobufConvert for #name {
+            impl #impl_generics ProtobufConvert for #name #ty_generics #where_clause {
                 type ProtoStruct = #pb_name;
 
                 fn from_pb(mut pb: Self::ProtoStruct) -> std::result::Result<Self, anyhow::Error> {
@@ -355,6 +3404,8 @@ impl ProtobufConvertEnum {
                 fn to_pb(&self) -> Self::ProtoStruct {
                     #to_pb_impl
                 }
+
+                #into_pb_impl
             }
         }
     }
@@ -410,11 +3461,27 @@ impl ProtobufConvertEnum {
 
         variant.name.to_string()
     }
+
+    /// Returns the variant of the generated oneof enum corresponding to the variant.
+    fn get_pb_variant(&self, variant: &ParsedVariant) -> Ident {
+        let variant_name = self.get_variant_name(variant);
+        if matches!(self.attrs.backend, Backend::Prost | Backend::Protobuf3) {
+            Ident::new(&variant_name.to_camel_case(), Span::call_site())
+        } else {
+            Ident::new(&variant_name, Span::call_site())
+        }
+    }
 }
 
 impl ToTokens for ProtobufConvertEnum {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
-        let pb_convert = self.impl_protobuf_convert();
+        let pb_convert = if self.attrs.any {
+            let pb_convert = self.impl_protobuf_convert_any();
+            quote! { #pb_convert }
+        } else {
+            let pb_convert = self.impl_protobuf_convert();
+            quote! { #pb_convert }
+        };
         let conversions = self.impl_enum_conversions();
 
         let expanded = quote! {
@@ -437,6 +3504,7 @@ impl FromDeriveInput for ProtobufConvert {
             Data::Struct(data) => Ok(ProtobufConvert::Struct(
                 ProtobufConvertStruct::from_derive_input(
                     input.ident.clone(),
+                    input.generics.clone(),
                     data,
                     input.attrs.as_ref(),
                 )?,
@@ -444,6 +3512,7 @@ impl FromDeriveInput for ProtobufConvert {
             Data::Enum(data) => Ok(ProtobufConvert::Enum(
                 ProtobufConvertEnum::from_derive_input(
                     input.ident.clone(),
+                    input.generics.clone(),
                     data,
                     input.attrs.as_ref(),
                 )?,
@@ -463,6 +3532,20 @@ impl ProtobufConvert {
         }
     }
 
+    fn generics(&self) -> &Generics {
+        match self {
+            ProtobufConvert::Enum(inner) => &inner.generics,
+            ProtobufConvert::Struct(inner) => &inner.generics,
+        }
+    }
+
+    fn backend(&self) -> Backend {
+        match self {
+            ProtobufConvert::Enum(inner) => inner.attrs.backend,
+            ProtobufConvert::Struct(inner) => inner.attrs.backend,
+        }
+    }
+
     fn serde_needed(&self) -> bool {
         match self {
             ProtobufConvert::Enum(inner) => inner.attrs.serde_pb_convert,
@@ -472,8 +3555,15 @@ impl ProtobufConvert {
 
     fn implement_serde_protobuf_convert(&self) -> impl ToTokens {
         let name = self.name();
+        let generics = self.generics();
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+        let mut de_generics = generics.clone();
+        de_generics.params.insert(0, syn::parse_quote!('de));
+        let (de_impl_generics, _, _) = de_generics.split_for_impl();
+
         quote! {
-            impl serde::Serialize for #name {
+            impl #impl_generics serde::Serialize for #name #ty_generics #where_clause {
                 fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
                 where
                     S: serde::Serializer,
@@ -482,12 +3572,12 @@ impl ProtobufConvert {
                 }
             }
 
-            impl<'de> serde::Deserialize<'de> for #name {
+            impl #de_impl_generics serde::Deserialize<'de> for #name #ty_generics #where_clause {
                 fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
                 where
                     D: serde::Deserializer<'de>,
                 {
-                    let pb = <#name as ProtobufConvert>::ProtoStruct::deserialize(deserializer)?;
+                    let pb = <Self as ProtobufConvert>::ProtoStruct::deserialize(deserializer)?;
                     ProtobufConvert::from_pb(pb).map_err(serde::de::Error::custom)
                 }
             }
@@ -516,11 +3606,17 @@ impl ToTokens for ProtobufConvert {
             quote! {}
         };
 
+        let message_import = if self.backend().implements_message() {
+            quote! { use protobuf::Message as _ProtobufMessage; }
+        } else {
+            quote! {}
+        };
+
         let expanded = quote! {
             mod #mod_name {
                 use super::*;
 
-                use protobuf::Message as _ProtobufMessage;
+                #message_import
 
                 #protobuf_convert
                 #serde_traits
@@ -0,0 +1,382 @@
+// Copyright 2019 The Exonum Team, 2019 Witnet Foundation
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use proc_macro::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{parse::Parser, punctuated::Punctuated, Ident, Token};
+
+/// Optional conversions of the crates selected by the arguments of the macro, which
+/// must be dependencies of the crate invoking it.
+#[derive(Debug, Default)]
+struct Conversions {
+    chrono: bool,
+    time: bool,
+    serde_json: bool,
+}
+
+impl Conversions {
+    fn parse(input: TokenStream) -> syn::Result<Self> {
+        let args = Punctuated::<Ident, Token![,]>::parse_terminated.parse(input)?;
+        let mut conversions = Self::default();
+        for arg in args {
+            let enabled = match arg.to_string().as_ref() {
+                "chrono" => &mut conversions.chrono,
+                "time" => &mut conversions.time,
+                "serde_json" => &mut conversions.serde_json,
+                _ => {
+                    return Err(syn::Error::new(
+                        arg.span(),
+                        "impl_well_known_types: expected `chrono`, `time` or `serde_json`",
+                    ))
+                }
+            };
+            if *enabled {
+                return Err(syn::Error::new(
+                    arg.span(),
+                    "impl_well_known_types: duplicate argument",
+                ));
+            }
+            *enabled = true;
+        }
+        Ok(conversions)
+    }
+}
+
+fn impl_std_time() -> impl ToTokens {
+    quote! {
+        impl ProtobufConvert for std::time::SystemTime {
+            type ProtoStruct = protobuf::well_known_types::Timestamp;
+
+            fn to_pb(&self) -> Self::ProtoStruct {
+                let seconds = |duration: std::time::Duration| {
+                    <i64 as std::convert::TryFrom<u64>>::try_from(duration.as_secs())
+                        .unwrap_or(i64::MAX)
+                };
+                let (seconds, nanos) = match self.duration_since(std::time::UNIX_EPOCH) {
+                    Ok(since_epoch) => (seconds(since_epoch), since_epoch.subsec_nanos()),
+                    Err(e) => {
+                        let before_epoch = e.duration();
+                        match before_epoch.subsec_nanos() {
+                            0 => (-seconds(before_epoch), 0),
+                            nanos => (-seconds(before_epoch) - 1, 1_000_000_000 - nanos),
+                        }
+                    }
+                };
+                new_timestamp(seconds, nanos as i32)
+            }
+
+            fn from_pb(pb: Self::ProtoStruct) -> std::result::Result<Self, anyhow::Error> {
+                check_timestamp(&pb)?;
+
+                let seconds = std::time::Duration::from_secs(pb.get_seconds().unsigned_abs());
+                let nanos = std::time::Duration::from_nanos(pb.get_nanos() as u64);
+                let time = if pb.get_seconds() >= 0 {
+                    std::time::UNIX_EPOCH.checked_add(seconds)
+                } else {
+                    std::time::UNIX_EPOCH.checked_sub(seconds)
+                };
+                time.and_then(|time| time.checked_add(nanos))
+                    .ok_or_else(|| anyhow::anyhow!("Timestamp is not representable as SystemTime"))
+            }
+        }
+
+        impl ProtobufConvert for std::time::Duration {
+            type ProtoStruct = protobuf::well_known_types::Duration;
+
+            fn to_pb(&self) -> Self::ProtoStruct {
+                let seconds = <i64 as std::convert::TryFrom<u64>>::try_from(self.as_secs())
+                    .unwrap_or(i64::MAX);
+                new_duration(seconds, self.subsec_nanos() as i32)
+            }
+
+            fn from_pb(pb: Self::ProtoStruct) -> std::result::Result<Self, anyhow::Error> {
+                check_duration(&pb)?;
+                if pb.get_seconds() < 0 || pb.get_nanos() < 0 {
+                    anyhow::bail!("Negative duration cannot be converted into std::time::Duration");
+                }
+                Ok(std::time::Duration::new(pb.get_seconds() as u64, pb.get_nanos() as u32))
+            }
+        }
+    }
+}
+
+fn impl_chrono() -> impl ToTokens {
+    quote! {
+        impl ProtobufConvert for ::chrono::DateTime<::chrono::Utc> {
+            type ProtoStruct = protobuf::well_known_types::Timestamp;
+
+            fn to_pb(&self) -> Self::ProtoStruct {
+                // Leap seconds are represented with nanoseconds exceeding one second.
+                let nanos = self.timestamp_subsec_nanos().min(999_999_999);
+                new_timestamp(self.timestamp(), nanos as i32)
+            }
+
+            fn from_pb(pb: Self::ProtoStruct) -> std::result::Result<Self, anyhow::Error> {
+                check_timestamp(&pb)?;
+                let nanos = pb.get_nanos() as u32;
+                ::chrono::TimeZone::timestamp_opt(&::chrono::Utc, pb.get_seconds(), nanos)
+                    .single()
+                    .ok_or_else(|| anyhow::anyhow!("Timestamp is not representable as DateTime"))
+            }
+        }
+
+        impl ProtobufConvert for ::chrono::Duration {
+            type ProtoStruct = protobuf::well_known_types::Duration;
+
+            fn to_pb(&self) -> Self::ProtoStruct {
+                let seconds = self.num_seconds();
+                let nanos = (*self - ::chrono::Duration::seconds(seconds))
+                    .num_nanoseconds()
+                    .unwrap_or_default();
+                new_duration(seconds, nanos as i32)
+            }
+
+            fn from_pb(pb: Self::ProtoStruct) -> std::result::Result<Self, anyhow::Error> {
+                check_duration(&pb)?;
+                Ok(::chrono::Duration::seconds(pb.get_seconds())
+                    + ::chrono::Duration::nanoseconds(pb.get_nanos().into()))
+            }
+        }
+    }
+}
+
+fn impl_time() -> impl ToTokens {
+    quote! {
+        impl ProtobufConvert for ::time::OffsetDateTime {
+            type ProtoStruct = protobuf::well_known_types::Timestamp;
+
+            fn to_pb(&self) -> Self::ProtoStruct {
+                new_timestamp(self.unix_timestamp(), self.nanosecond() as i32)
+            }
+
+            fn from_pb(pb: Self::ProtoStruct) -> std::result::Result<Self, anyhow::Error> {
+                check_timestamp(&pb)?;
+                let nanos =
+                    i128::from(pb.get_seconds()) * 1_000_000_000 + i128::from(pb.get_nanos());
+                ::time::OffsetDateTime::from_unix_timestamp_nanos(nanos).map_err(|e| {
+                    anyhow::anyhow!("Timestamp is not representable as OffsetDateTime: {}", e)
+                })
+            }
+        }
+
+        impl ProtobufConvert for ::time::Duration {
+            type ProtoStruct = protobuf::well_known_types::Duration;
+
+            fn to_pb(&self) -> Self::ProtoStruct {
+                new_duration(self.whole_seconds(), self.subsec_nanoseconds())
+            }
+
+            fn from_pb(pb: Self::ProtoStruct) -> std::result::Result<Self, anyhow::Error> {
+                check_duration(&pb)?;
+                Ok(::time::Duration::new(pb.get_seconds(), pb.get_nanos()))
+            }
+        }
+    }
+}
+
+fn impl_serde_json() -> impl ToTokens {
+    quote! {
+        /// Largest integer up to which all integers are exactly representable by `f64`.
+        const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+        /// Converts the protobuf number into JSON. Integral values which are exactly
+        /// representable by `f64` are converted into JSON integers, so that they round-trip.
+        fn json_number(value: f64) -> std::result::Result<::serde_json::Value, anyhow::Error> {
+            if value.fract() == 0.0 && value.abs() <= MAX_SAFE_INTEGER {
+                return Ok(::serde_json::Value::Number((value as i64).into()));
+            }
+            ::serde_json::Number::from_f64(value)
+                .map(::serde_json::Value::Number)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Non-finite number {} cannot be represented in JSON", value)
+                })
+        }
+
+        impl ProtobufConvert for ::serde_json::Value {
+            type ProtoStruct = protobuf::well_known_types::Value;
+
+            fn to_pb(&self) -> Self::ProtoStruct {
+                let mut pb = Self::ProtoStruct::new();
+                match self {
+                    ::serde_json::Value::Null => {
+                        pb.set_null_value(protobuf::well_known_types::NullValue::NULL_VALUE)
+                    }
+                    ::serde_json::Value::Bool(value) => pb.set_bool_value(*value),
+                    // Numbers which do not fit into `f64` with the `arbitrary_precision` feature
+                    // of serde_json are written as NaN, which is rejected by `from_pb`.
+                    ::serde_json::Value::Number(value) => {
+                        pb.set_number_value(value.as_f64().unwrap_or(f64::NAN))
+                    }
+                    ::serde_json::Value::String(value) => pb.set_string_value(value.clone()),
+                    ::serde_json::Value::Array(items) => {
+                        let mut list = protobuf::well_known_types::ListValue::new();
+                        list.set_values(items.iter().map(ProtobufConvert::to_pb).collect());
+                        pb.set_list_value(list);
+                    }
+                    ::serde_json::Value::Object(map) => pb.set_struct_value(map.to_pb()),
+                }
+                pb
+            }
+
+            fn from_pb(pb: Self::ProtoStruct) -> std::result::Result<Self, anyhow::Error> {
+                use protobuf::well_known_types::Value_oneof_kind as Kind;
+
+                Ok(match pb.kind {
+                    None | Some(Kind::null_value(_)) => ::serde_json::Value::Null,
+                    Some(Kind::bool_value(value)) => ::serde_json::Value::Bool(value),
+                    Some(Kind::number_value(value)) => json_number(value)?,
+                    Some(Kind::string_value(value)) => ::serde_json::Value::String(value),
+                    Some(Kind::struct_value(value)) => {
+                        ::serde_json::Value::Object(ProtobufConvert::from_pb(value)?)
+                    }
+                    Some(Kind::list_value(mut list)) => ::serde_json::Value::Array(
+                        list.take_values()
+                            .into_iter()
+                            .map(ProtobufConvert::from_pb)
+                            .collect::<std::result::Result<_, _>>()?,
+                    ),
+                })
+            }
+        }
+
+        impl ProtobufConvert for ::serde_json::Map<String, ::serde_json::Value> {
+            type ProtoStruct = protobuf::well_known_types::Struct;
+
+            fn to_pb(&self) -> Self::ProtoStruct {
+                let mut pb = Self::ProtoStruct::new();
+                pb.set_fields(
+                    self.iter()
+                        .map(|(key, value)| (key.clone(), value.to_pb()))
+                        .collect(),
+                );
+                pb
+            }
+
+            fn from_pb(mut pb: Self::ProtoStruct) -> std::result::Result<Self, anyhow::Error> {
+                pb.take_fields()
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, ProtobufConvert::from_pb(value)?)))
+                    .collect()
+            }
+        }
+    }
+}
+
+pub fn implement_well_known_types(input: TokenStream) -> TokenStream {
+    let conversions = match Conversions::parse(input) {
+        Ok(conversions) => conversions,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let std_time = impl_std_time();
+    let chrono = if conversions.chrono {
+        let chrono = impl_chrono();
+        quote! { #chrono }
+    } else {
+        quote! {}
+    };
+    let time = if conversions.time {
+        let time = impl_time();
+        quote! { #time }
+    } else {
+        quote! {}
+    };
+
+    let serde_json = if conversions.serde_json {
+        let serde_json = impl_serde_json();
+        quote! { #serde_json }
+    } else {
+        quote! {}
+    };
+
+    let tokens = quote! {
+        mod pb_convert_impl_well_known_types {
+            use super::*;
+
+            /// Seconds of 0001-01-01T00:00:00Z.
+            const MIN_TIMESTAMP_SECONDS: i64 = -62_135_596_800;
+            /// Seconds of 9999-12-31T23:59:59Z.
+            const MAX_TIMESTAMP_SECONDS: i64 = 253_402_300_799;
+            /// Seconds of approximately 10,000 years.
+            const MAX_DURATION_SECONDS: i64 = 315_576_000_000;
+
+            /// Creates the timestamp, clamping it into the range allowed by the specification.
+            fn new_timestamp(seconds: i64, nanos: i32) -> protobuf::well_known_types::Timestamp {
+                let (seconds, nanos) = if seconds < MIN_TIMESTAMP_SECONDS {
+                    (MIN_TIMESTAMP_SECONDS, 0)
+                } else if seconds > MAX_TIMESTAMP_SECONDS {
+                    (MAX_TIMESTAMP_SECONDS, 999_999_999)
+                } else {
+                    (seconds, nanos)
+                };
+
+                let mut pb = protobuf::well_known_types::Timestamp::new();
+                pb.set_seconds(seconds);
+                pb.set_nanos(nanos);
+                pb
+            }
+
+            /// Creates the duration, clamping it into the range allowed by the specification.
+            fn new_duration(seconds: i64, nanos: i32) -> protobuf::well_known_types::Duration {
+                let (seconds, nanos) = if seconds < -MAX_DURATION_SECONDS {
+                    (-MAX_DURATION_SECONDS, -999_999_999)
+                } else if seconds > MAX_DURATION_SECONDS {
+                    (MAX_DURATION_SECONDS, 999_999_999)
+                } else {
+                    (seconds, nanos)
+                };
+
+                let mut pb = protobuf::well_known_types::Duration::new();
+                pb.set_seconds(seconds);
+                pb.set_nanos(nanos);
+                pb
+            }
+
+            fn check_timestamp(
+                pb: &protobuf::well_known_types::Timestamp,
+            ) -> std::result::Result<(), anyhow::Error> {
+                if !(0..1_000_000_000).contains(&pb.get_nanos()) {
+                    anyhow::bail!("Timestamp nanos are out of range: {}", pb.get_nanos());
+                }
+                if !(MIN_TIMESTAMP_SECONDS..=MAX_TIMESTAMP_SECONDS).contains(&pb.get_seconds()) {
+                    anyhow::bail!("Timestamp seconds are out of range: {}", pb.get_seconds());
+                }
+                Ok(())
+            }
+
+            fn check_duration(
+                pb: &protobuf::well_known_types::Duration,
+            ) -> std::result::Result<(), anyhow::Error> {
+                if !(-999_999_999..=999_999_999).contains(&pb.get_nanos()) {
+                    anyhow::bail!("Duration nanos are out of range: {}", pb.get_nanos());
+                }
+                if !(-MAX_DURATION_SECONDS..=MAX_DURATION_SECONDS).contains(&pb.get_seconds()) {
+                    anyhow::bail!("Duration seconds are out of range: {}", pb.get_seconds());
+                }
+                if (pb.get_seconds() < 0 && pb.get_nanos() > 0)
+                    || (pb.get_seconds() > 0 && pb.get_nanos() < 0)
+                {
+                    anyhow::bail!("Duration seconds and nanos have different signs");
+                }
+                Ok(())
+            }
+
+            #std_time
+            #chrono
+            #time
+            #serde_json
+        }
+    };
+    tokens.into()
+}
@@ -0,0 +1,265 @@
+// Copyright 2019 The Exonum Team, 2019 Witnet Foundation
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use darling::FromMeta;
+use proc_macro2::{Ident, Span, TokenStream};
+use quote::quote;
+
+/// Largest field number allowed by the protobuf specification.
+pub const MAX_FIELD_NUMBER: u32 = 0x1fff_ffff;
+
+/// Type of the protobuf field, which determines its encoding on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtoType {
+    Double,
+    Float,
+    Int32,
+    Int64,
+    Uint32,
+    Uint64,
+    Sint32,
+    Sint64,
+    Fixed32,
+    Fixed64,
+    Sfixed32,
+    Sfixed64,
+    Bool,
+    String,
+    Bytes,
+    Enum,
+    Message,
+}
+
+const PROTO_TYPES: &[ProtoType] = &[
+    ProtoType::Double,
+    ProtoType::Float,
+    ProtoType::Int32,
+    ProtoType::Int64,
+    ProtoType::Uint32,
+    ProtoType::Uint64,
+    ProtoType::Sint32,
+    ProtoType::Sint64,
+    ProtoType::Fixed32,
+    ProtoType::Fixed64,
+    ProtoType::Sfixed32,
+    ProtoType::Sfixed64,
+    ProtoType::Bool,
+    ProtoType::String,
+    ProtoType::Bytes,
+    ProtoType::Enum,
+    ProtoType::Message,
+];
+
+impl FromMeta for ProtoType {
+    fn from_string(value: &str) -> darling::Result<Self> {
+        PROTO_TYPES
+            .iter()
+            .copied()
+            .find(|proto_type| proto_type.name() == value)
+            .ok_or_else(|| darling::Error::unknown_value(value))
+    }
+}
+
+impl ProtoType {
+    /// Name of the type in the proto files.
+    pub fn name(self) -> &'static str {
+        match self {
+            ProtoType::Double => "double",
+            ProtoType::Float => "float",
+            ProtoType::Int32 => "int32",
+            ProtoType::Int64 => "int64",
+            ProtoType::Uint32 => "uint32",
+            ProtoType::Uint64 => "uint64",
+            ProtoType::Sint32 => "sint32",
+            ProtoType::Sint64 => "sint64",
+            ProtoType::Fixed32 => "fixed32",
+            ProtoType::Fixed64 => "fixed64",
+            ProtoType::Sfixed32 => "sfixed32",
+            ProtoType::Sfixed64 => "sfixed64",
+            ProtoType::Bool => "bool",
+            ProtoType::String => "string",
+            ProtoType::Bytes => "bytes",
+            ProtoType::Enum => "enum",
+            ProtoType::Message => "message",
+        }
+    }
+
+    /// Type of the field in the descriptors of rust-protobuf 2.x.
+    pub fn descriptor_type(self) -> TokenStream {
+        let name = Ident::new(
+            &format!("TYPE_{}", self.name().to_uppercase()),
+            Span::call_site(),
+        );
+        quote! { protobuf::descriptor::FieldDescriptorProto_Type::#name }
+    }
+
+    /// Returns `true` if the values of the type are scalars, i.e. they are not strings,
+    /// bytes or messages.
+    pub fn is_scalar(self) -> bool {
+        !matches!(
+            self,
+            ProtoType::String | ProtoType::Bytes | ProtoType::Message
+        )
+    }
+
+    pub fn wire_type(self) -> TokenStream {
+        match self {
+            ProtoType::Double | ProtoType::Fixed64 | ProtoType::Sfixed64 => {
+                quote! { protobuf::wire_format::WireTypeFixed64 }
+            }
+            ProtoType::Float | ProtoType::Fixed32 | ProtoType::Sfixed32 => {
+                quote! { protobuf::wire_format::WireTypeFixed32 }
+            }
+            ProtoType::String | ProtoType::Bytes | ProtoType::Message => {
+                quote! { protobuf::wire_format::WireTypeLengthDelimited }
+            }
+            _ => quote! { protobuf::wire_format::WireTypeVarint },
+        }
+    }
+
+    /// Converts the reference to the Rust value into the value written on the wire.
+    pub fn convert(self, value: TokenStream) -> TokenStream {
+        match self {
+            ProtoType::String => quote! { AsRef::<str>::as_ref(#value) },
+            ProtoType::Bytes => quote! { AsRef::<[u8]>::as_ref(#value) },
+            ProtoType::Enum => {
+                quote! { protobuf::ProtobufEnum::value(&ProtobufConvert::to_pb(#value)) }
+            }
+            ProtoType::Message => value,
+            _ => quote! { ProtobufConvert::to_pb(#value) },
+        }
+    }
+
+    /// Checks whether the converted value is the default one, which is not written
+    /// for the singular proto3 fields. Messages are always written if present.
+    pub fn is_default(self, value: TokenStream) -> Option<TokenStream> {
+        match self {
+            ProtoType::Double | ProtoType::Float => Some(quote! { #value == 0.0 }),
+            ProtoType::Bool => Some(quote! { !#value }),
+            ProtoType::String | ProtoType::Bytes => Some(quote! { #value.is_empty() }),
+            ProtoType::Message => None,
+            _ => Some(quote! { #value == 0 }),
+        }
+    }
+
+    /// Encoded length of the converted value without the tag.
+    pub fn encoded_len(self, value: TokenStream) -> TokenStream {
+        match self {
+            ProtoType::Double | ProtoType::Fixed64 | ProtoType::Sfixed64 => quote! { 8 },
+            ProtoType::Float | ProtoType::Fixed32 | ProtoType::Sfixed32 => quote! { 4 },
+            ProtoType::Sint32 | ProtoType::Sint64 => {
+                quote! { protobuf::rt::ProtobufVarintZigzag::len_varint_zigzag(&#value) }
+            }
+            ProtoType::String | ProtoType::Bytes => quote! {{
+                let len = #value.len() as u32;
+                protobuf::rt::compute_raw_varint32_size(len) + len
+            }},
+            // The length of the message is stored in the slot reserved before the lengths
+            // of its own nested messages, in the order they are written.
+            ProtoType::Message => quote! {{
+                let slot = sizes.len();
+                sizes.push(0);
+                let len = #value.pb_convert_compute_sizes(sizes);
+                sizes[slot] = len;
+                protobuf::rt::compute_raw_varint32_size(len) + len
+            }},
+            _ => quote! { protobuf::rt::ProtobufVarint::len_varint(&#value) },
+        }
+    }
+
+    /// Reads the value of the singular field with the `wire_type` from `is` into `target`.
+    /// Unknown enum values are stored in `unknown_fields`, as rust-protobuf does.
+    pub fn read(self, target: TokenStream) -> TokenStream {
+        match self {
+            ProtoType::String => quote! {
+                protobuf::rt::read_singular_proto3_string_into(wire_type, is, &mut #target)?;
+            },
+            ProtoType::Bytes => quote! {
+                protobuf::rt::read_singular_proto3_bytes_into(wire_type, is, &mut #target)?;
+            },
+            ProtoType::Enum => quote! {
+                protobuf::rt::read_proto3_enum_with_unknown_fields_into(
+                    wire_type,
+                    is,
+                    &mut #target,
+                    field_number,
+                    &mut unknown_fields,
+                )?;
+            },
+            // Messages are decoded by the fields.
+            ProtoType::Message => unreachable!(),
+            _ => {
+                let wire_type = self.wire_type();
+                let read = Ident::new(&format!("read_{}", self.name()), Span::call_site());
+                quote! {
+                    if wire_type != #wire_type {
+                        return Err(protobuf::rt::unexpected_wire_type(wire_type).into());
+                    }
+                    #target = is.#read()?;
+                }
+            }
+        }
+    }
+
+    /// Reads the packed or unpacked values of the repeated field from `is`
+    /// and appends them to `target`.
+    pub fn read_repeated(self, target: TokenStream) -> TokenStream {
+        match self {
+            ProtoType::String | ProtoType::Bytes => {
+                let read = Ident::new(&format!("read_{}", self.name()), Span::call_site());
+                quote! {
+                    if wire_type != protobuf::wire_format::WireTypeLengthDelimited {
+                        return Err(protobuf::rt::unexpected_wire_type(wire_type).into());
+                    }
+                    #target.push(is.#read()?);
+                }
+            }
+            ProtoType::Enum => quote! {
+                protobuf::rt::read_repeated_enum_with_unknown_fields_into(
+                    wire_type,
+                    is,
+                    &mut #target,
+                    field_number,
+                    &mut unknown_fields,
+                )?;
+            },
+            ProtoType::Message => unreachable!(),
+            _ => {
+                let read = Ident::new(
+                    &format!("read_repeated_{}_into", self.name()),
+                    Span::call_site(),
+                );
+                quote! { protobuf::rt::#read(wire_type, is, &mut #target)?; }
+            }
+        }
+    }
+
+    /// Writes the converted value without the tag.
+    pub fn write(self, value: TokenStream) -> TokenStream {
+        match self {
+            ProtoType::Message => quote! {
+                let len = match sizes.next() {
+                    Some(&len) => len,
+                    None => ProtobufConvert::encoded_len(#value),
+                };
+                os.write_raw_varint32(len)?;
+                #value.pb_convert_encode_with_sizes(os, sizes)?;
+            },
+            _ => {
+                let write = Ident::new(&format!("write_{}_no_tag", self.name()), Span::call_site());
+                quote! { os.#write(#value)?; }
+            }
+        }
+    }
+}
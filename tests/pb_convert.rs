@@ -14,9 +14,20 @@
 
 use serde::{Deserialize, Serialize};
 
-use crate::proto::ProtobufConvert;
+use crate::proto::{ProtobufConvert, UnknownMaskPath};
+use protobuf::{well_known_types::FieldMask, CodedInputStream, Message as _, UnknownFields};
 use protobuf_convert::ProtobufConvert;
-use std::convert::TryFrom;
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashSet},
+    convert::TryFrom,
+    marker::PhantomData,
+    net::{IpAddr, SocketAddr},
+    num::NonZeroU32,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 mod proto;
 
@@ -29,6 +40,54 @@ struct SkipFieldsMessage {
     name: String,
 }
 
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SkipFieldsMessage", wire)]
+struct ForwardedMessage {
+    #[protobuf_convert(number = 1)]
+    id: u32,
+    #[protobuf_convert(unknown_fields)]
+    extra: UnknownFields,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SimpleMessage", wire)]
+struct MisnumberedMessage {
+    #[protobuf_convert(number = 3)]
+    id: u32,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SimpleMessage", wire)]
+struct MistypedMessage {
+    #[protobuf_convert(number = 1, proto_type = "fixed32")]
+    id: u32,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::WireMessage", wire)]
+struct UnpackedMessage {
+    #[protobuf_convert(number = 3)]
+    values: BTreeSet<u32>,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::OrderMessage", wire)]
+struct ReorderedMessage {
+    #[protobuf_convert(number = 1)]
+    first: u32,
+    #[protobuf_convert(number = 2)]
+    second: u32,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::ProfileMessage", deny_unknown_fields, wire)]
+struct StrictProfile {
+    #[protobuf_convert(number = 1, proto_type = "message")]
+    user: Message,
+    #[protobuf_convert(number = 2)]
+    version: u32,
+}
+
 #[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
 #[protobuf_convert(source = "proto::SimpleMessage")]
 struct Message {
@@ -36,10 +95,47 @@ struct Message {
     name: String,
 }
 
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SimpleMessage", register)]
+struct RegisteredMessage {
+    id: u32,
+    name: String,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SkipFieldsMessage", register)]
+struct RegisteredSkipFieldsMessage {
+    id: u32,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SimpleMessage", merge)]
+struct MergedMessage {
+    id: u32,
+    name: String,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SimpleMessage", reuse_buffers)]
+struct ReusedMessage {
+    id: u32,
+    name: String,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SimpleMessage", wire)]
+struct WireSimpleMessage {
+    #[protobuf_convert(number = 1)]
+    id: u32,
+    #[protobuf_convert(number = 2)]
+    name: String,
+}
+
 #[derive(Debug, ProtobufConvert, Eq, PartialEq)]
 #[protobuf_convert(
     source = "proto::EnumMessage",
     impl_from_trait,
+    into_pb,
     rename(case = "snake_case")
 )]
 enum EnumMessage {
@@ -63,6 +159,33 @@ enum EnumMessageWithUpperCaseField {
     Simple(Message),
 }
 
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(any, into_pb)]
+enum AnyPayload {
+    Simple(RegisteredMessage),
+    Skip(RegisteredSkipFieldsMessage),
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SimpleMessage", register)]
+struct LegacyMessage {
+    id: u32,
+    name: String,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(any)]
+enum AmbiguousPayload {
+    Current(RegisteredMessage),
+    Legacy(LegacyMessage),
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::AnyMessage")]
+struct AnyMessage {
+    payload: AnyPayload,
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 enum CustomId {
     First = 5,
@@ -100,6 +223,325 @@ mod custom_id_pb_convert {
     }
 }
 
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::NestedPathMessage", field_mask, merge, reuse_buffers)]
+struct FlatMessage {
+    id: u32,
+    #[protobuf_convert(path = "meta.created.seconds")]
+    created: Option<u64>,
+    #[protobuf_convert(path = "meta.author")]
+    author: String,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::ProfileMessage", merge)]
+struct Profile {
+    user: MergedMessage,
+    version: u32,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::ProfileMessage", deny_unknown_fields, merge)]
+struct StrictMergedProfile {
+    user: MergedMessage,
+    version: u32,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::ProfileMessage", merge)]
+struct ReplacedProfile {
+    user: Message,
+    version: u32,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::ProfileMessage", reuse_buffers)]
+struct ReusedProfile {
+    user: ReusedMessage,
+    version: u32,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::NestedPathMessage")]
+struct RequiredPathMessage {
+    #[protobuf_convert(path = "meta.created.seconds", required)]
+    created: u64,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::DerivedFieldsMessage", into_pb, reuse_buffers, wire)]
+struct DerivedFieldsMessage {
+    #[protobuf_convert(number = 1)]
+    text: String,
+    #[protobuf_convert(number = 2, to_pb_only, compute = "DerivedFieldsMessage::text_len")]
+    length: u32,
+    #[protobuf_convert(number = 3, from_pb_only)]
+    version: u32,
+}
+
+impl DerivedFieldsMessage {
+    fn text_len(&self) -> u32 {
+        self.text.len() as u32
+    }
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SimpleMessage")]
+struct DefaultValuesMessage {
+    #[protobuf_convert(default_as_none)]
+    id: Option<u32>,
+    #[protobuf_convert(default_value = "String::from(\"unnamed\")")]
+    name: String,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::TreeNode", field_mask, into_pb, wire)]
+struct TreeNode {
+    #[protobuf_convert(number = 1)]
+    value: u32,
+    #[protobuf_convert(number = 2, proto_type = "message")]
+    left: Option<Box<TreeNode>>,
+    #[protobuf_convert(number = 3, proto_type = "message")]
+    right: Option<Box<TreeNode>>,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::PointersMessage", into_pb, reuse_buffers)]
+struct PointersMessage<'a> {
+    boxed: Box<Message>,
+    counted: Rc<Message>,
+    shared: Arc<Message>,
+    borrowed: Cow<'a, Message>,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::ProfileMessage")]
+struct GenericProfile<T, M>
+where
+    T: ProtobufConvert<ProtoStruct = proto::SimpleMessage>,
+{
+    user: Box<T>,
+    version: u32,
+    #[protobuf_convert(skip)]
+    marker: PhantomData<M>,
+}
+
+/// Marker type without `ProtobufConvert`, which is only used by a skipped field.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Unconverted;
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SetsMessage")]
+struct CustomContainersMessage {
+    peers: sorted::HashSet<u32>,
+    #[protobuf_convert(plain)]
+    capabilities: Rc<[String]>,
+}
+
+mod sorted {
+    use super::*;
+
+    /// Set stored as a sorted vector, which is named like the standard set.
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub struct HashSet<T>(pub Vec<T>);
+
+    impl ProtobufConvert for HashSet<u32> {
+        type ProtoStruct = Vec<u32>;
+
+        fn to_pb(&self) -> Self::ProtoStruct {
+            self.0.clone()
+        }
+
+        fn from_pb(pb: Self::ProtoStruct) -> Result<Self, anyhow::Error> {
+            if pb.windows(2).any(|pair| pair[0] >= pair[1]) {
+                return Err(anyhow::anyhow!("Set elements are not sorted"));
+            }
+            Ok(Self(pb))
+        }
+    }
+}
+
+impl ProtobufConvert for Rc<[String]> {
+    type ProtoStruct = Vec<String>;
+
+    fn to_pb(&self) -> Self::ProtoStruct {
+        self.to_vec()
+    }
+
+    fn from_pb(pb: Self::ProtoStruct) -> Result<Self, anyhow::Error> {
+        Ok(pb.into())
+    }
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::ArraysMessage", into_pb, reuse_buffers, wire)]
+struct ArraysMessage {
+    #[protobuf_convert(number = 1)]
+    hash: [u8; 32],
+    #[protobuf_convert(number = 2)]
+    values: [u32; 3],
+    #[protobuf_convert(number = 3, proto_type = "message")]
+    pair: [ReusedMessage; 2],
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SetsMessage", into_pb, reuse_buffers, wire)]
+struct SetsMessage {
+    #[protobuf_convert(number = 1, deny_duplicates)]
+    peers: BTreeSet<u32>,
+    #[protobuf_convert(number = 2)]
+    capabilities: HashSet<String>,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::NarrowMessage", into_pb)]
+struct NarrowMessage {
+    #[protobuf_convert(via = "u32")]
+    small: u8,
+    #[protobuf_convert(via = "u64")]
+    size: usize,
+    #[protobuf_convert(via = "u32")]
+    non_zero: NonZeroU32,
+    #[protobuf_convert(via = "i32")]
+    delta: i16,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::AddressMessage", reuse_buffers)]
+struct AddressMessage {
+    #[protobuf_convert(display_fromstr)]
+    ip: IpAddr,
+    #[protobuf_convert(display_fromstr, default_as_none)]
+    socket: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::WrappersMessage", reuse_buffers)]
+struct WrappersMessage {
+    #[protobuf_convert(wrapper)]
+    balance: Option<u64>,
+    #[protobuf_convert(wrapper)]
+    alias: Option<String>,
+    #[protobuf_convert(wrapper, via = "u32")]
+    port: Option<u16>,
+}
+
+/// Newtype converted into a scalar protobuf field.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Height(u32);
+
+impl ProtobufConvert for Height {
+    type ProtoStruct = u32;
+
+    fn to_pb(&self) -> Self::ProtoStruct {
+        self.0
+    }
+
+    fn from_pb(pb: Self::ProtoStruct) -> Result<Self, anyhow::Error> {
+        Ok(Self(pb))
+    }
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::SimpleMessage", into_pb, reuse_buffers)]
+struct HeightMessage {
+    #[protobuf_convert(proto_type = "uint32")]
+    id: Height,
+    name: String,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::LogMessage", reuse_buffers, wire)]
+struct LogMessage {
+    #[protobuf_convert(number = 1, enumeration)]
+    level: proto::Level,
+    #[protobuf_convert(number = 2)]
+    text: String,
+    #[protobuf_convert(number = 3, proto_type = "message")]
+    source: WireSimpleMessage,
+    #[protobuf_convert(number = 4, proto_type = "message")]
+    related: [WireSimpleMessage; 2],
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::WireMessage", wire)]
+struct WireMessage {
+    #[protobuf_convert(number = 6)]
+    payload: Vec<u8>,
+    #[protobuf_convert(number = 1, proto_type = "sint32")]
+    offset: i32,
+    #[protobuf_convert(number = 2, proto_type = "fixed64")]
+    checksum: u64,
+    #[protobuf_convert(number = 3, packed)]
+    values: BTreeSet<u32>,
+    #[protobuf_convert(number = 4)]
+    labels: [String; 2],
+    #[protobuf_convert(number = 5, proto_type = "message")]
+    parent: Option<Box<WireSimpleMessage>>,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::fields::Tag")]
+struct Tag {
+    r#type: String,
+    weight: u32,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::fields::Tag")]
+struct RenamedTag {
+    #[protobuf_convert(getter = "get_field_type", setter = "set_field_type")]
+    kind: String,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::fields::Item", accessors = "fields", into_pb)]
+struct Item {
+    tag: Tag,
+    tags: [Tag; 2],
+    #[protobuf_convert(wrapper)]
+    amount: Option<u64>,
+    data: Vec<u8>,
+    #[protobuf_convert(path = "tag.type")]
+    tag_type: String,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::fields::Tag", accessors = "fields")]
+struct FieldsTag {
+    #[protobuf_convert(getter = "field_type", setter = "field_type")]
+    kind: String,
+    weight: u32,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::TimeMessage")]
+struct TimeMessage {
+    created: SystemTime,
+    timeout: Duration,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::TimeMessage")]
+struct ChronoTimeMessage {
+    created: chrono::DateTime<chrono::Utc>,
+    timeout: chrono::Duration,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::TimeMessage")]
+struct TimeCrateMessage {
+    created: time::OffsetDateTime,
+    timeout: time::Duration,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, PartialEq)]
+#[protobuf_convert(source = "proto::JsonMessage")]
+struct JsonMessage {
+    payload: serde_json::Map<String, serde_json::Value>,
+    extra: serde_json::Value,
+}
+
 #[test]
 fn simple_message_roundtrip() {
     let message = Message {
@@ -125,6 +567,88 @@ fn skip_field_message() {
     assert!(de_message.name.is_empty());
 }
 
+#[test]
+fn unknown_fields() {
+    let message = Message {
+        id: 1,
+        name: "Alice".into(),
+    };
+    let bytes = message.to_pb().write_to_bytes().unwrap();
+
+    // The message is relayed by a node which is not aware of the `name` field.
+    let pb_forwarded = proto::SkipFieldsMessage::parse_from_bytes(&bytes).unwrap();
+    let forwarded = ForwardedMessage::from_pb(pb_forwarded).unwrap();
+    assert_eq!(forwarded.id, 1);
+    assert!(forwarded.extra.get(2).is_some());
+
+    let bytes = forwarded.to_pb().write_to_bytes().unwrap();
+    let pb_message = proto::SimpleMessage::parse_from_bytes(&bytes).unwrap();
+    assert_eq!(Message::from_pb(pb_message).unwrap(), message);
+}
+
+#[test]
+fn deny_unknown_fields() {
+    let profile = StrictProfile {
+        user: Message {
+            id: 1,
+            name: "Alice".into(),
+        },
+        version: 3,
+    };
+    assert_eq!(StrictProfile::from_pb(profile.to_pb()).unwrap(), profile);
+
+    let mut pb_profile = profile.to_pb();
+    pb_profile.mut_unknown_fields().add_varint(5, 1);
+    pb_profile.mut_unknown_fields().add_varint(4, 1);
+    let e = StrictProfile::from_pb(pb_profile).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Unknown fields with tags [4, 5] in message `ProfileMessage`"
+    );
+
+    // Unknown fields of the nested messages are rejected as well.
+    let mut pb_profile = profile.to_pb();
+    pb_profile.mut_user().mut_unknown_fields().add_varint(7, 1);
+    let e = StrictProfile::from_pb(pb_profile).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Unknown fields with tags [7] in message `SimpleMessage`"
+    );
+
+    // Merged messages are checked before any field is updated.
+    let mut merged = StrictMergedProfile {
+        user: MergedMessage {
+            id: 1,
+            name: "Alice".into(),
+        },
+        version: 3,
+    };
+    let mut pb_profile = proto::ProfileMessage::new();
+    pb_profile.set_version(4);
+    pb_profile.mut_unknown_fields().add_varint(5, 1);
+    let e = merged.merge_from_pb(pb_profile, None).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Unknown fields with tags [5] in message `ProfileMessage`"
+    );
+    assert_eq!(merged.version, 3);
+
+    let mut pb_profile = proto::ProfileMessage::new();
+    pb_profile.mut_user().set_name("Bob".into());
+    pb_profile.mut_user().mut_unknown_fields().add_varint(7, 1);
+    let e = merged.merge_from_pb(pb_profile, None).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Unknown fields with tags [7] in message `SimpleMessage`"
+    );
+    assert_eq!(merged.user.name, "Alice");
+
+    let mut pb_profile = proto::ProfileMessage::new();
+    pb_profile.mut_user().set_name("Bob".into());
+    merged.merge_from_pb(pb_profile, None).unwrap();
+    assert_eq!(merged.user.name, "Bob");
+}
+
 #[test]
 fn custom_message_roundtrip() {
     let message = CustomMessage {
@@ -163,6 +687,26 @@ fn enum_message() {
         EnumMessage::Skip(msg) => assert_eq!(msg.id, message.id),
         _ => panic!("Deserialized message has wrong type"),
     }
+
+    let similar = EnumMessageWithSimilarFields::Skip(Message {
+        id: 2,
+        name: "similar".into(),
+    });
+    let pb_similar = similar.to_pb();
+    assert!(pb_similar.has_skip());
+    assert_eq!(
+        EnumMessageWithSimilarFields::from_pb(pb_similar).unwrap(),
+        similar
+    );
+
+    let upper = EnumMessageWithUpperCaseField::Simple(Message {
+        id: 3,
+        name: "upper".into(),
+    });
+    assert_eq!(
+        EnumMessageWithUpperCaseField::from_pb(upper.to_pb()).unwrap(),
+        upper
+    );
 }
 
 #[test]
@@ -189,3 +733,1230 @@ fn from_trait() {
         .to_string()
         .contains("Expected variant Simple, but got Skip"));
 }
+
+#[test]
+fn nested_path_message() {
+    let message = FlatMessage {
+        id: 1,
+        created: Some(1_500_000_000),
+        author: "Alice".into(),
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(
+        pb_message.get_meta().get_created().get_seconds(),
+        1_500_000_000
+    );
+    assert_eq!(pb_message.get_meta().get_author(), "Alice");
+    assert_eq!(FlatMessage::from_pb(pb_message).unwrap(), message);
+
+    // Absent intermediate messages are decoded as `None` and are not created on `to_pb`.
+    let message = FlatMessage {
+        created: None,
+        ..message
+    };
+    let pb_message = message.to_pb();
+    assert!(!pb_message.get_meta().has_created());
+    assert_eq!(FlatMessage::from_pb(pb_message).unwrap(), message);
+
+    let e = RequiredPathMessage::from_pb(proto::NestedPathMessage::new()).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Missing message `meta` required by field `created`"
+    );
+    let message = RequiredPathMessage { created: 10 };
+    let de_message = RequiredPathMessage::from_pb(message.to_pb()).unwrap();
+    assert_eq!(de_message, message);
+}
+
+#[test]
+fn one_directional_fields() {
+    let message = DerivedFieldsMessage {
+        text: "Hello".into(),
+        length: 0,
+        version: 2,
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_length(), 5);
+    assert_eq!(pb_message.get_version(), 0);
+
+    let mut pb_message = proto::DerivedFieldsMessage::new();
+    pb_message.set_text("Hello, world".into());
+    pb_message.set_length(100);
+    pb_message.set_version(3);
+    let de_message = DerivedFieldsMessage::from_pb(pb_message).unwrap();
+    assert_eq!(de_message.length, 12);
+    assert_eq!(de_message.version, 3);
+}
+
+#[test]
+fn default_values() {
+    let message = DefaultValuesMessage {
+        id: None,
+        name: "Bob".into(),
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_id(), 0);
+    assert_eq!(DefaultValuesMessage::from_pb(pb_message).unwrap(), message);
+
+    let message = DefaultValuesMessage {
+        id: Some(5),
+        name: "Bob".into(),
+    };
+    assert_eq!(
+        DefaultValuesMessage::from_pb(message.to_pb()).unwrap(),
+        message
+    );
+
+    let de_message = DefaultValuesMessage::from_pb(proto::SimpleMessage::new()).unwrap();
+    assert_eq!(
+        de_message,
+        DefaultValuesMessage {
+            id: None,
+            name: "unnamed".into(),
+        }
+    );
+}
+
+#[test]
+fn recursive_message() {
+    let leaf = |value| TreeNode {
+        value,
+        left: None,
+        right: None,
+    };
+    let tree = TreeNode {
+        value: 1,
+        left: Some(Box::new(TreeNode {
+            value: 2,
+            left: Some(Box::new(leaf(3))),
+            right: None,
+        })),
+        right: Some(Box::new(leaf(4))),
+    };
+    let pb_tree = tree.to_pb();
+    assert!(pb_tree.get_left().has_left());
+    assert!(!pb_tree.get_left().has_right());
+    assert_eq!(TreeNode::from_pb(pb_tree).unwrap(), tree);
+}
+
+#[test]
+fn field_mask() {
+    let mask = |paths: &[&str]| {
+        let mut mask = FieldMask::new();
+        mask.set_paths(paths.iter().map(|&path| path.to_owned()).collect());
+        mask
+    };
+
+    let message = FlatMessage {
+        id: 1,
+        created: Some(1_500_000_000),
+        author: "Alice".into(),
+    };
+    let pb_message = message.to_pb_masked(&mask(&["meta"])).unwrap();
+    assert_eq!(pb_message.get_id(), 0);
+    assert_eq!(pb_message.get_meta(), message.to_pb().get_meta());
+
+    let pb_message = message.to_pb_masked(&mask(&["id", "meta.author"])).unwrap();
+    assert_eq!(pb_message.get_id(), 1);
+    assert_eq!(pb_message.get_meta().get_author(), "Alice");
+    assert!(!pb_message.get_meta().has_created());
+
+    let pb_message = message.to_pb_masked(&mask(&[])).unwrap();
+    assert_eq!(pb_message, message.to_pb());
+
+    let unknown_path = |e: anyhow::Error| e.downcast::<UnknownMaskPath>().unwrap().path;
+    let e = message.to_pb_masked(&mask(&["meta.name"])).unwrap_err();
+    assert_eq!(e.to_string(), "Unknown field mask path `meta.name`");
+    assert_eq!(unknown_path(e), "meta.name");
+
+    let leaf = |value| TreeNode {
+        value,
+        left: None,
+        right: None,
+    };
+    let tree = TreeNode {
+        value: 1,
+        left: Some(Box::new(TreeNode {
+            value: 2,
+            left: Some(Box::new(leaf(3))),
+            right: None,
+        })),
+        right: Some(Box::new(leaf(4))),
+    };
+    let pb_tree = tree.to_pb_masked(&mask(&["left.value", "right"])).unwrap();
+    assert_eq!(pb_tree.get_value(), 0);
+    assert_eq!(pb_tree.get_left().get_value(), 2);
+    assert!(!pb_tree.get_left().has_left());
+    assert_eq!(pb_tree.get_right().get_value(), 4);
+
+    let e = tree.to_pb_masked(&mask(&["left.size"])).unwrap_err();
+    assert_eq!(unknown_path(e), "left.size");
+    let e = tree.to_pb_masked(&mask(&["left.left.size"])).unwrap_err();
+    assert_eq!(unknown_path(e), "left.left.size");
+
+    // Paths under the absent values are checked against the field type.
+    let pb_tree = tree.to_pb_masked(&mask(&["left.right.value"])).unwrap();
+    assert!(!pb_tree.get_left().has_right());
+    let e = tree.to_pb_masked(&mask(&["left.right.size"])).unwrap_err();
+    assert_eq!(unknown_path(e), "left.right.size");
+    let e = leaf(5)
+        .to_pb_masked(&mask(&["right.left.size"]))
+        .unwrap_err();
+    assert_eq!(unknown_path(e), "right.left.size");
+}
+
+#[test]
+fn merge_from_pb() {
+    let mask = |paths: &[&str]| {
+        let mut mask = FieldMask::new();
+        mask.set_paths(paths.iter().map(|&path| path.to_owned()).collect());
+        mask
+    };
+
+    let mut profile = Profile {
+        user: MergedMessage {
+            id: 1,
+            name: "Alice".into(),
+        },
+        version: 1,
+    };
+
+    // Only the fields set in the message are merged, recursing into the nested messages.
+    let mut pb_profile = proto::ProfileMessage::new();
+    pb_profile.mut_user().set_name("Bob".into());
+    profile.merge_from_pb(pb_profile.clone(), None).unwrap();
+    assert_eq!(profile.user.id, 1);
+    assert_eq!(profile.user.name, "Bob");
+    assert_eq!(profile.version, 1);
+
+    // Fields selected by the mask are overwritten even with the default values.
+    pb_profile.mut_user().set_id(2);
+    profile
+        .merge_from_pb(pb_profile.clone(), Some(&mask(&["user.id", "version"])))
+        .unwrap();
+    assert_eq!(
+        profile,
+        Profile {
+            user: MergedMessage {
+                id: 2,
+                name: "Bob".into(),
+            },
+            version: 0,
+        }
+    );
+
+    let e = profile
+        .merge_from_pb(pb_profile, Some(&mask(&["user.email"])))
+        .unwrap_err();
+    assert_eq!(e.downcast::<UnknownMaskPath>().unwrap().path, "user.email");
+
+    // Messages without `merge` are replaced if present, even if they are empty.
+    let mut profile = ReplacedProfile {
+        user: Message {
+            id: 1,
+            name: "Alice".into(),
+        },
+        version: 1,
+    };
+    let mut pb_profile = proto::ProfileMessage::new();
+    pb_profile.set_version(2);
+    profile.merge_from_pb(pb_profile.clone(), None).unwrap();
+    assert_eq!(profile.user.name, "Alice");
+    assert_eq!(profile.version, 2);
+
+    pb_profile.mut_user();
+    profile.merge_from_pb(pb_profile, None).unwrap();
+    assert_eq!(
+        profile.user,
+        Message {
+            id: 0,
+            name: String::new(),
+        }
+    );
+
+    let mut message = FlatMessage {
+        id: 1,
+        created: Some(1_500_000_000),
+        author: "Alice".into(),
+    };
+    let mut pb_message = proto::NestedPathMessage::new();
+    pb_message.set_id(2);
+    message.merge_from_pb(pb_message.clone(), None).unwrap();
+    assert_eq!(message.id, 2);
+    assert_eq!(message.created, Some(1_500_000_000));
+    assert_eq!(message.author, "Alice");
+
+    message
+        .merge_from_pb(pb_message, Some(&mask(&["meta.created"])))
+        .unwrap();
+    assert_eq!(message.created, None);
+    assert_eq!(message.author, "Alice");
+}
+
+#[test]
+fn pointer_fields() {
+    let inner = Message {
+        id: 1,
+        name: "shared".into(),
+    };
+    let message = PointersMessage {
+        boxed: Box::new(inner.clone()),
+        counted: Rc::new(inner.clone()),
+        shared: Arc::new(inner.clone()),
+        borrowed: Cow::Borrowed(&inner),
+    };
+    let de_message = PointersMessage::from_pb(message.to_pb()).unwrap();
+    assert_eq!(de_message, message);
+
+    let profile: GenericProfile<Message, Unconverted> = GenericProfile {
+        user: Box::new(inner),
+        version: 1,
+        marker: PhantomData,
+    };
+    assert_eq!(GenericProfile::from_pb(profile.to_pb()).unwrap(), profile);
+}
+
+#[test]
+fn array_fields() {
+    let message = ArraysMessage {
+        hash: [7; 32],
+        values: [1, 2, 3],
+        pair: [
+            ReusedMessage {
+                id: 1,
+                name: "first".into(),
+            },
+            ReusedMessage {
+                id: 2,
+                name: "second".into(),
+            },
+        ],
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_hash(), &[7; 32][..]);
+    assert_eq!(ArraysMessage::from_pb(pb_message).unwrap(), message);
+
+    let mut pb_message = message.to_pb();
+    pb_message.set_hash(vec![1; 31]);
+    let e = ArraysMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Invalid length of field `hash`: expected 32, got 31"
+    );
+
+    let mut pb_message = message.to_pb();
+    pb_message.mut_pair().push(proto::SimpleMessage::new());
+    let e = ArraysMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Invalid length of field `pair`: expected 2, got 3"
+    );
+}
+
+#[test]
+fn set_fields() {
+    let message = SetsMessage {
+        peers: vec![3, 1, 2].into_iter().collect(),
+        capabilities: vec!["read".to_owned(), "write".to_owned()]
+            .into_iter()
+            .collect(),
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_peers(), &[1, 2, 3][..]);
+    assert_eq!(SetsMessage::from_pb(pb_message).unwrap(), message);
+
+    // Duplicates are collapsed unless they are denied.
+    let mut pb_message = message.to_pb();
+    pb_message.mut_capabilities().push("read".into());
+    let de_message = SetsMessage::from_pb(pb_message).unwrap();
+    assert_eq!(de_message.capabilities, message.capabilities);
+
+    let mut pb_message = message.to_pb();
+    pb_message.set_peers(vec![1, 2, 1]);
+    let e = SetsMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Duplicate element in field `peers` at index 2"
+    );
+
+    // Other types named like the sets and the `plain` fields use their own implementations.
+    let message = CustomContainersMessage {
+        peers: sorted::HashSet(vec![1, 2, 3]),
+        capabilities: vec!["read".to_owned(), "read".to_owned()].into(),
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_capabilities(), &["read", "read"][..]);
+    assert_eq!(
+        CustomContainersMessage::from_pb(pb_message).unwrap(),
+        message
+    );
+    let mut pb_message = message.to_pb();
+    pb_message.set_peers(vec![2, 1]);
+    let e = CustomContainersMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(e.to_string(), "Set elements are not sorted");
+}
+
+#[test]
+fn narrowing_conversions() {
+    let message = NarrowMessage {
+        small: 255,
+        size: usize::MAX,
+        non_zero: NonZeroU32::new(7).unwrap(),
+        delta: -300,
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_small(), 255);
+    assert_eq!(pb_message.get_size(), usize::MAX as u64);
+    assert_eq!(pb_message.get_delta(), -300);
+    assert_eq!(NarrowMessage::from_pb(pb_message).unwrap(), message);
+
+    let mut pb_message = message.to_pb();
+    pb_message.set_small(256);
+    let e = NarrowMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(e.to_string(), "Value 256 of field `small` is out of range");
+
+    let mut pb_message = message.to_pb();
+    pb_message.set_non_zero(0);
+    let e = NarrowMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(e.to_string(), "Value 0 of field `non_zero` is out of range");
+}
+
+#[test]
+fn buffer_reuse() {
+    let profile = ReusedProfile {
+        user: ReusedMessage {
+            id: 1,
+            name: "a".repeat(64),
+        },
+        version: 2,
+    };
+    let mut pb_profile = proto::ProfileMessage::new();
+    profile.to_pb_into(&mut pb_profile);
+    assert_eq!(pb_profile, profile.to_pb());
+
+    let short = ReusedProfile {
+        user: ReusedMessage {
+            id: 2,
+            name: "b".into(),
+        },
+        version: 3,
+    };
+    short.to_pb_into(&mut pb_profile);
+    assert_eq!(pb_profile, short.to_pb());
+    assert!(pb_profile.mut_user().mut_name().capacity() >= 64);
+
+    // Decoding into an existing value keeps the buffers of its strings.
+    let mut decoded = profile.clone();
+    let name_ptr = decoded.user.name.as_ptr();
+    decoded.from_pb_into(short.to_pb()).unwrap();
+    assert_eq!(decoded, short);
+    assert_eq!(decoded.user.name.as_ptr(), name_ptr);
+    assert!(decoded.user.name.capacity() >= 64);
+
+    // Fields absent from the value are cleared in the reused message.
+    let flat = FlatMessage {
+        id: 1,
+        created: Some(5),
+        author: "Alice".into(),
+    };
+    let mut pb_flat = flat.to_pb();
+    let flat = FlatMessage {
+        created: None,
+        author: String::new(),
+        ..flat
+    };
+    flat.to_pb_into(&mut pb_flat);
+    assert_eq!(pb_flat, flat.to_pb());
+    let mut decoded = FlatMessage {
+        id: 0,
+        created: Some(1),
+        author: "Bob".into(),
+    };
+    decoded.from_pb_into(pb_flat).unwrap();
+    assert_eq!(decoded, flat);
+
+    let inner = ReusedMessage {
+        id: 1,
+        name: "reused".into(),
+    };
+    let arrays = ArraysMessage {
+        hash: [7; 32],
+        values: [1, 2, 3],
+        pair: [inner.clone(), inner.clone()],
+    };
+    let long = ReusedMessage {
+        id: 2,
+        name: "c".repeat(64),
+    };
+    let mut decoded = ArraysMessage {
+        pair: [long.clone(), long],
+        ..arrays.clone()
+    };
+    let mut pb_arrays = decoded.to_pb();
+    arrays.to_pb_into(&mut pb_arrays);
+    assert_eq!(pb_arrays, arrays.to_pb());
+    // Elements of the repeated fields are overwritten in place.
+    assert!(pb_arrays.mut_pair()[1].mut_name().capacity() >= 64);
+    // So are the elements of the arrays.
+    let name_ptr = decoded.pair[1].name.as_ptr();
+    decoded.from_pb_into(pb_arrays).unwrap();
+    assert_eq!(decoded, arrays);
+    assert_eq!(decoded.pair[1].name.as_ptr(), name_ptr);
+    let mut pb_arrays = arrays.to_pb();
+    pb_arrays.mut_pair().push(proto::SimpleMessage::new());
+    let e = decoded.from_pb_into(pb_arrays).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Invalid length of field `pair`: expected 2, got 3"
+    );
+
+    let inner = Message {
+        id: 1,
+        name: "reused".into(),
+    };
+    let pointers = PointersMessage {
+        boxed: Box::new(inner.clone()),
+        counted: Rc::new(inner.clone()),
+        shared: Arc::new(inner.clone()),
+        borrowed: Cow::Borrowed(&inner),
+    };
+    let mut pb_pointers = proto::PointersMessage::new();
+    pointers.to_pb_into(&mut pb_pointers);
+    assert_eq!(pb_pointers, pointers.to_pb());
+    let mut decoded = PointersMessage::from_pb(proto::PointersMessage::new()).unwrap();
+    decoded.from_pb_into(pb_pointers).unwrap();
+    assert_eq!(decoded, pointers);
+
+    let derived = DerivedFieldsMessage {
+        text: "hello".into(),
+        length: 5,
+        version: 3,
+    };
+    let mut pb_derived = proto::DerivedFieldsMessage::new();
+    derived.to_pb_into(&mut pb_derived);
+    assert_eq!(pb_derived, derived.to_pb());
+    let mut decoded = derived.clone();
+    decoded.from_pb_into(pb_derived.clone()).unwrap();
+    assert_eq!(decoded, DerivedFieldsMessage::from_pb(pb_derived).unwrap());
+}
+
+#[test]
+fn consuming_conversion() {
+    let inner = Message {
+        id: 1,
+        name: "moved".into(),
+    };
+    let pointers = PointersMessage {
+        boxed: Box::new(inner.clone()),
+        counted: Rc::new(inner.clone()),
+        shared: Arc::new(inner.clone()),
+        borrowed: Cow::Owned(inner.clone()),
+    };
+    assert_eq!(pointers.to_pb(), pointers.clone().into_pb());
+
+    let tree = TreeNode {
+        value: 1,
+        left: Some(Box::new(TreeNode {
+            value: 2,
+            left: None,
+            right: None,
+        })),
+        right: None,
+    };
+    assert_eq!(tree.to_pb(), tree.clone().into_pb());
+
+    let derived = DerivedFieldsMessage {
+        text: "hello".into(),
+        length: 0,
+        version: 3,
+    };
+    let moved = derived.clone();
+    let text_ptr = moved.text.as_ptr();
+    let pb_derived = moved.into_pb();
+    assert_eq!(pb_derived.get_length(), 5);
+    assert_eq!(pb_derived, derived.to_pb());
+    // The buffers of the strings are moved into the message.
+    assert_eq!(pb_derived.get_text().as_ptr(), text_ptr);
+
+    let reused = ReusedMessage {
+        id: 1,
+        name: "moved".into(),
+    };
+    let arrays = ArraysMessage {
+        hash: [7; 32],
+        values: [1, 2, 3],
+        pair: [reused.clone(), reused],
+    };
+    assert_eq!(arrays.to_pb(), arrays.clone().into_pb());
+
+    let sets = SetsMessage {
+        peers: vec![3, 1, 2].into_iter().collect(),
+        capabilities: vec!["read".to_owned()].into_iter().collect(),
+    };
+    let moved = sets.clone();
+    let capability_ptr = moved.capabilities.iter().next().unwrap().as_ptr();
+    let pb_sets = moved.into_pb();
+    assert_eq!(pb_sets.get_capabilities()[0].as_ptr(), capability_ptr);
+    assert_eq!(SetsMessage::from_pb(pb_sets).unwrap(), sets);
+
+    let tag = Tag {
+        r#type: "color".into(),
+        weight: 3,
+    };
+    let item = Item {
+        tag: tag.clone(),
+        tags: [tag.clone(), tag],
+        amount: None,
+        data: vec![1; 64],
+        tag_type: "color".into(),
+    };
+    let moved = item.clone();
+    let data_ptr = moved.data.as_ptr();
+    let pb_item = moved.into_pb();
+    assert_eq!(pb_item.data.as_ptr(), data_ptr);
+    assert_eq!(pb_item.data.capacity(), 64);
+
+    let narrow = NarrowMessage {
+        small: 255,
+        size: 1 << 40,
+        non_zero: NonZeroU32::new(7).unwrap(),
+        delta: -300,
+    };
+    assert_eq!(narrow.to_pb(), narrow.clone().into_pb());
+
+    let variant = EnumMessage::Simple(inner.clone());
+    assert_eq!(
+        variant.to_pb(),
+        EnumMessage::Simple(inner.clone()).into_pb()
+    );
+    let payload = AnyPayload::Simple(RegisteredMessage {
+        id: 1,
+        name: "moved".into(),
+    });
+    assert_eq!(payload.to_pb(), payload.clone().into_pb());
+}
+
+#[test]
+fn display_fromstr_fields() {
+    let message = AddressMessage {
+        ip: "127.0.0.1".parse().unwrap(),
+        socket: Some("10.0.0.1:21337".parse().unwrap()),
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_ip(), "127.0.0.1");
+    assert_eq!(pb_message.get_socket(), "10.0.0.1:21337");
+    assert_eq!(AddressMessage::from_pb(pb_message).unwrap(), message);
+
+    let message = AddressMessage {
+        socket: None,
+        ..message
+    };
+    assert_eq!(AddressMessage::from_pb(message.to_pb()).unwrap(), message);
+
+    let mut pb_message = message.to_pb();
+    pb_message.set_ip("localhost".into());
+    let e = AddressMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Failed to parse field `ip`: invalid IP address syntax"
+    );
+}
+
+#[test]
+fn any_fields() {
+    let message = AnyMessage {
+        payload: AnyPayload::Simple(RegisteredMessage {
+            id: 1,
+            name: "any".into(),
+        }),
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(
+        pb_message.get_payload().get_type_url(),
+        "type.googleapis.com/SimpleMessage"
+    );
+    assert_eq!(AnyMessage::from_pb(pb_message).unwrap(), message);
+
+    let message = AnyMessage {
+        payload: AnyPayload::Skip(RegisteredSkipFieldsMessage { id: 2 }),
+    };
+    assert_eq!(AnyMessage::from_pb(message.to_pb()).unwrap(), message);
+
+    let mut pb_message = message.to_pb();
+    pb_message
+        .mut_payload()
+        .set_type_url("example.com/Unknown".into());
+    let e = AnyMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(e.to_string(), "Unknown type URL `example.com/Unknown`");
+
+    assert_eq!(RegisteredMessage::proto_full_name(), "SimpleMessage");
+    assert_eq!(LegacyMessage::proto_full_name(), "SimpleMessage");
+    let payload = AmbiguousPayload::Legacy(LegacyMessage {
+        id: 3,
+        name: "legacy".into(),
+    });
+    let e = AmbiguousPayload::from_pb(payload.to_pb()).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Type `SimpleMessage` is registered by several variants of `AmbiguousPayload`"
+    );
+}
+
+#[test]
+fn wrapper_fields() {
+    let message = WrappersMessage {
+        balance: Some(0),
+        alias: Some("alice".into()),
+        port: Some(21337),
+    };
+    let pb_message = message.to_pb();
+    assert!(pb_message.has_balance());
+    assert_eq!(pb_message.get_balance().get_value(), 0);
+    assert_eq!(pb_message.get_alias().get_value(), "alice");
+    assert_eq!(WrappersMessage::from_pb(pb_message).unwrap(), message);
+
+    let message = WrappersMessage {
+        balance: None,
+        alias: None,
+        port: None,
+    };
+    let pb_message = message.to_pb();
+    assert!(!pb_message.has_balance());
+    assert!(!pb_message.has_alias());
+    assert_eq!(WrappersMessage::from_pb(pb_message).unwrap(), message);
+
+    let mut pb_message = message.to_pb();
+    pb_message.mut_port().set_value(1 << 16);
+    let e = WrappersMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(e.to_string(), "Value 65536 of field `port` is out of range");
+}
+
+#[test]
+fn wire_encoding() {
+    fn assert_encoding<T: ProtobufConvert>(value: &T)
+    where
+        T::ProtoStruct: protobuf::Message,
+    {
+        let expected = value.to_pb().write_to_bytes().unwrap();
+        let mut bytes = Vec::new();
+        let mut os = protobuf::CodedOutputStream::vec(&mut bytes);
+        value.encode(&mut os).unwrap();
+        os.flush().unwrap();
+        drop(os);
+        assert_eq!(bytes, expected);
+        assert_eq!(value.encoded_len() as usize, expected.len());
+    }
+
+    let message = WireSimpleMessage {
+        id: 300,
+        name: "source".into(),
+    };
+    assert_encoding(&message);
+    assert_encoding(&WireSimpleMessage {
+        id: 0,
+        name: String::new(),
+    });
+    assert_encoding(&LogMessage {
+        level: proto::Level::LEVEL_ERROR,
+        text: "failure".into(),
+        source: message.clone(),
+        related: [message.clone(), message.clone()],
+    });
+    assert_encoding(&TreeNode {
+        value: 1,
+        left: Some(Box::new(TreeNode {
+            value: 2,
+            left: None,
+            right: None,
+        })),
+        right: None,
+    });
+    let reused = ReusedMessage {
+        id: 300,
+        name: "source".into(),
+    };
+    assert_encoding(&ArraysMessage {
+        hash: [7; 32],
+        values: [0, 1, u32::MAX],
+        pair: [reused.clone(), reused],
+    });
+    assert_encoding(&SetsMessage {
+        peers: vec![3, 1, 2].into_iter().collect(),
+        capabilities: vec!["sync".to_owned()].into_iter().collect(),
+    });
+    assert_encoding(&DerivedFieldsMessage {
+        text: "hello".into(),
+        length: 0,
+        version: 3,
+    });
+
+    let mut pb_forwarded = proto::SimpleMessage::new();
+    pb_forwarded.set_id(1);
+    pb_forwarded.set_name("unknown".into());
+    let bytes = pb_forwarded.write_to_bytes().unwrap();
+    let pb_forwarded = proto::SkipFieldsMessage::parse_from_bytes(&bytes).unwrap();
+    let forwarded = ForwardedMessage::from_pb(pb_forwarded).unwrap();
+    assert_encoding(&forwarded);
+
+    let wire_message = WireMessage {
+        payload: vec![1, 2, 3],
+        offset: -5,
+        checksum: 0xdead_beef,
+        values: vec![1, 300, 70_000].into_iter().collect(),
+        labels: ["a".to_owned(), String::new()],
+        parent: Some(Box::new(message)),
+    };
+    assert_encoding(&wire_message);
+    assert_encoding(&WireMessage {
+        payload: Vec::new(),
+        offset: 0,
+        checksum: 0,
+        values: BTreeSet::new(),
+        labels: [String::new(), String::new()],
+        parent: None,
+    });
+}
+
+#[test]
+fn wire_decoding() {
+    fn decode<T>(bytes: &[u8]) -> Result<T, anyhow::Error>
+    where
+        T: ProtobufConvert,
+        T::ProtoStruct: protobuf::Message,
+    {
+        T::decode(&mut CodedInputStream::from_bytes(bytes))
+    }
+
+    fn assert_decoding<T>(value: &T)
+    where
+        T: ProtobufConvert + PartialEq + std::fmt::Debug,
+        T::ProtoStruct: protobuf::Message,
+    {
+        let bytes = value.to_pb().write_to_bytes().unwrap();
+        assert_eq!(&decode::<T>(&bytes).unwrap(), value);
+    }
+
+    let message = WireSimpleMessage {
+        id: 300,
+        name: "source".into(),
+    };
+    assert_decoding(&message);
+    assert_decoding(&LogMessage {
+        level: proto::Level::LEVEL_ERROR,
+        text: "failure".into(),
+        source: message.clone(),
+        related: [message.clone(), message.clone()],
+    });
+    assert_decoding(&TreeNode {
+        value: 1,
+        left: Some(Box::new(TreeNode {
+            value: 2,
+            left: None,
+            right: None,
+        })),
+        right: None,
+    });
+    let reused = ReusedMessage {
+        id: 300,
+        name: "source".into(),
+    };
+    assert_decoding(&ArraysMessage {
+        hash: [7; 32],
+        values: [0, 1, u32::MAX],
+        pair: [reused.clone(), reused.clone()],
+    });
+    assert_decoding(&SetsMessage {
+        peers: vec![3, 1, 2].into_iter().collect(),
+        capabilities: vec!["sync".to_owned()].into_iter().collect(),
+    });
+    assert_decoding(&WireMessage {
+        payload: vec![1, 2, 3],
+        offset: -5,
+        checksum: 0xdead_beef,
+        values: vec![1, 300, 70_000].into_iter().collect(),
+        labels: ["a".to_owned(), String::new()],
+        parent: Some(Box::new(message.clone())),
+    });
+
+    // Absent messages are decoded from their default value.
+    let profile = decode::<StrictProfile>(&[]).unwrap();
+    assert_eq!(profile.user, Message::from_pb(Default::default()).unwrap());
+
+    // Computed and skipped fields are handled as in `from_pb`.
+    let mut pb_derived = proto::DerivedFieldsMessage::new();
+    pb_derived.set_text("hello".into());
+    pb_derived.set_length(100);
+    pb_derived.set_version(3);
+    let bytes = pb_derived.write_to_bytes().unwrap();
+    assert_eq!(
+        decode::<DerivedFieldsMessage>(&bytes).unwrap(),
+        DerivedFieldsMessage::from_pb(pb_derived).unwrap()
+    );
+
+    // Conversion errors are the same as in `from_pb`.
+    let mut pb_arrays = ArraysMessage {
+        hash: [7; 32],
+        values: [0, 1, 2],
+        pair: [reused.clone(), reused.clone()],
+    }
+    .to_pb();
+    pb_arrays.mut_values().push(3);
+    let bytes = pb_arrays.write_to_bytes().unwrap();
+    assert_eq!(
+        decode::<ArraysMessage>(&bytes).unwrap_err().to_string(),
+        ArraysMessage::from_pb(pb_arrays).unwrap_err().to_string()
+    );
+    let mut pb_sets = proto::SetsMessage::new();
+    pb_sets.set_peers(vec![1, 1]);
+    let bytes = pb_sets.write_to_bytes().unwrap();
+    assert_eq!(
+        decode::<SetsMessage>(&bytes).unwrap_err().to_string(),
+        SetsMessage::from_pb(pb_sets).unwrap_err().to_string()
+    );
+
+    // Unknown fields are forwarded...
+    let bytes = message.to_pb().write_to_bytes().unwrap();
+    let forwarded = decode::<ForwardedMessage>(&bytes).unwrap();
+    assert_eq!(
+        forwarded,
+        ForwardedMessage::from_pb(proto::SkipFieldsMessage::parse_from_bytes(&bytes).unwrap())
+            .unwrap()
+    );
+    assert_eq!(forwarded.extra.iter().count(), 1);
+
+    // ...or rejected, including the ones of the nested messages.
+    let profile = StrictProfile {
+        user: Message {
+            id: 300,
+            name: "source".into(),
+        },
+        version: 3,
+    };
+    assert_decoding(&profile);
+    let mut pb_profile = profile.to_pb();
+    pb_profile.mut_user().mut_unknown_fields().add_varint(7, 1);
+    let bytes = pb_profile.write_to_bytes().unwrap();
+    assert_eq!(
+        decode::<StrictProfile>(&bytes).unwrap_err().to_string(),
+        "Unknown fields with tags [7] in message `SimpleMessage`"
+    );
+    let mut pb_profile = profile.to_pb();
+    pb_profile.mut_unknown_fields().add_varint(5, 1);
+    let bytes = pb_profile.write_to_bytes().unwrap();
+    assert_eq!(
+        decode::<StrictProfile>(&bytes).unwrap_err().to_string(),
+        "Unknown fields with tags [5] in message `ProfileMessage`"
+    );
+
+    // Nesting is limited like in `CodedInputStream`.
+    let mut tree = TreeNode {
+        value: 0,
+        left: None,
+        right: None,
+    };
+    for value in 1..=100 {
+        tree = TreeNode {
+            value,
+            left: Some(Box::new(tree)),
+            right: None,
+        };
+    }
+    assert_decoding(&tree);
+    let tree = TreeNode {
+        value: 101,
+        left: Some(Box::new(tree)),
+        right: None,
+    };
+    let bytes = tree.to_pb().write_to_bytes().unwrap();
+    assert!(proto::TreeNode::parse_from_bytes(&bytes).is_err());
+    assert!(decode::<TreeNode>(&bytes).is_err());
+
+    // Malformed input is reported by the input stream.
+    assert!(decode::<WireSimpleMessage>(&[0x08]).is_err());
+    assert!(decode::<WireSimpleMessage>(&[0x0a, 0x01, 0x00]).is_err());
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "Field `id` of message `SimpleMessage` has number 1, not 3")]
+fn wire_schema_number_mismatch() {
+    MisnumberedMessage { id: 1 }.encoded_len();
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(
+    expected = "Field `id` of message `SimpleMessage` has type TYPE_UINT32, not TYPE_FIXED32"
+)]
+fn wire_schema_type_mismatch() {
+    let mut is = protobuf::CodedInputStream::from_bytes(&[]);
+    MistypedMessage::decode(&mut is).unwrap();
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "Field `values` of message `WireMessage` is declared with \
+                           `[packed = true]`, but it is not marked with `packed`")]
+fn wire_schema_packed_mismatch() {
+    let values = vec![1].into_iter().collect();
+    UnpackedMessage { values }.encoded_len();
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(
+    expected = "Fields `first` and `second` of message `OrderMessage` are not \
+                           declared in the order of their numbers"
+)]
+fn wire_schema_order_mismatch() {
+    ReorderedMessage {
+        first: 1,
+        second: 2,
+    }
+    .encoded_len();
+}
+
+#[test]
+fn moved_fields() {
+    let message = WireSimpleMessage {
+        id: 1,
+        name: "source".into(),
+    };
+    let log = LogMessage {
+        level: proto::Level::LEVEL_ERROR,
+        text: "failure".into(),
+        source: message.clone(),
+        related: [message.clone(), message],
+    };
+    let pb_log = log.to_pb();
+    assert_eq!(pb_log.get_level(), proto::Level::LEVEL_ERROR);
+    assert_eq!(pb_log.get_source().get_name(), "source");
+
+    // Strings and nested messages are moved out of the decoded message with their buffers.
+    let text_ptr = pb_log.get_text().as_ptr();
+    let source_ptr = pb_log.get_source().get_name().as_ptr();
+    let related_ptr = pb_log.get_related()[1].get_name().as_ptr();
+    let decoded = LogMessage::from_pb(pb_log).unwrap();
+    assert_eq!(decoded, log);
+    assert_eq!(decoded.text.as_ptr(), text_ptr);
+    assert_eq!(decoded.source.name.as_ptr(), source_ptr);
+    assert_eq!(decoded.related[1].name.as_ptr(), related_ptr);
+
+    // Newtypes over scalars are marked with `proto_type` and read with `get_x()`.
+    let message = HeightMessage {
+        id: Height(7),
+        name: "tip".into(),
+    };
+    let pb_message = message.clone().into_pb();
+    assert_eq!(pb_message.get_id(), 7);
+    let mut decoded = HeightMessage {
+        id: Height(0),
+        name: String::new(),
+    };
+    decoded.from_pb_into(pb_message.clone()).unwrap();
+    assert_eq!(decoded, message);
+    let mut pb_reused = proto::SimpleMessage::new();
+    message.to_pb_into(&mut pb_reused);
+    assert_eq!(pb_reused, pb_message);
+}
+
+#[test]
+fn accessors() {
+    let tag = Tag {
+        r#type: "color".into(),
+        weight: 3,
+    };
+    let pb_tag = tag.to_pb();
+    assert_eq!(pb_tag.get_field_type(), "color");
+    assert_eq!(Tag::from_pb(pb_tag.clone()).unwrap(), tag);
+
+    let renamed = RenamedTag::from_pb(pb_tag.clone()).unwrap();
+    assert_eq!(renamed.kind, "color");
+    assert_eq!(renamed.to_pb().get_field_type(), "color");
+    let fields_tag = FieldsTag::from_pb(pb_tag).unwrap();
+    assert_eq!(fields_tag.kind, "color");
+    assert_eq!(fields_tag.to_pb().field_type, "color");
+
+    let item = Item {
+        tag: tag.clone(),
+        tags: [tag.clone(), tag],
+        amount: Some(0),
+        data: vec![1, 2],
+        tag_type: "color".into(),
+    };
+    let pb_item = item.to_pb();
+    assert_eq!(pb_item.tags.len(), 2);
+    assert_eq!(pb_item.get_amount().get_value(), 0);
+    assert!(pb_item.has_amount());
+    assert_eq!(Item::from_pb(pb_item).unwrap(), item);
+
+    let item = Item {
+        amount: None,
+        ..item
+    };
+    let pb_item = item.to_pb();
+    assert!(!pb_item.has_amount());
+    assert_eq!(pb_item, item.clone().into_pb());
+    assert_eq!(Item::from_pb(pb_item).unwrap(), item);
+}
+
+#[test]
+fn time_fields() {
+    let message = TimeMessage {
+        created: UNIX_EPOCH + Duration::new(1_500_000_000, 500),
+        timeout: Duration::from_millis(1500),
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_created().get_seconds(), 1_500_000_000);
+    assert_eq!(pb_message.get_timeout().get_nanos(), 500_000_000);
+    assert_eq!(TimeMessage::from_pb(pb_message).unwrap(), message);
+
+    // Time before the Unix epoch.
+    let message = TimeMessage {
+        created: UNIX_EPOCH - Duration::new(10, 250_000_000),
+        ..message
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_created().get_seconds(), -11);
+    assert_eq!(pb_message.get_created().get_nanos(), 750_000_000);
+    assert_eq!(TimeMessage::from_pb(pb_message).unwrap(), message);
+
+    let mut pb_message = message.to_pb();
+    pb_message.mut_timeout().set_seconds(-1);
+    pb_message.mut_timeout().set_nanos(0);
+    let e = TimeMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Negative duration cannot be converted into std::time::Duration"
+    );
+
+    let mut pb_message = message.to_pb();
+    pb_message.mut_created().set_nanos(1_000_000_000);
+    let e = TimeMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Timestamp nanos are out of range: 1000000000"
+    );
+
+    // Values at the limits of the protobuf ranges are kept, and the ones beyond are clamped.
+    let min_created = UNIX_EPOCH - Duration::from_secs(62_135_596_800);
+    let max_created = UNIX_EPOCH + Duration::new(253_402_300_799, 999_999_999);
+    let max_timeout = Duration::new(315_576_000_000, 999_999_999);
+    let message = TimeMessage {
+        created: min_created,
+        timeout: max_timeout,
+    };
+    assert_eq!(TimeMessage::from_pb(message.to_pb()).unwrap(), message);
+    let message = TimeMessage {
+        created: max_created,
+        ..message
+    };
+    assert_eq!(TimeMessage::from_pb(message.to_pb()).unwrap(), message);
+
+    let message = TimeMessage {
+        created: min_created - Duration::from_nanos(1),
+        timeout: max_timeout + Duration::from_nanos(1),
+    };
+    let clamped = TimeMessage::from_pb(message.to_pb()).unwrap();
+    assert_eq!(clamped.created, min_created);
+    assert_eq!(clamped.timeout, max_timeout);
+    let message = TimeMessage {
+        created: max_created + Duration::from_secs(1),
+        timeout: Duration::MAX,
+    };
+    let clamped = TimeMessage::from_pb(message.to_pb()).unwrap();
+    assert_eq!(clamped.created, max_created);
+    assert_eq!(clamped.timeout, max_timeout);
+}
+
+#[test]
+fn chrono_fields() {
+    use chrono::TimeZone;
+
+    let message = ChronoTimeMessage {
+        created: chrono::Utc.timestamp_opt(-100, 5).unwrap(),
+        timeout: chrono::Duration::milliseconds(-1500),
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_timeout().get_seconds(), -1);
+    assert_eq!(pb_message.get_timeout().get_nanos(), -500_000_000);
+    assert_eq!(ChronoTimeMessage::from_pb(pb_message).unwrap(), message);
+
+    let mut pb_message = message.to_pb();
+    pb_message.mut_timeout().set_nanos(500_000_000);
+    let e = ChronoTimeMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Duration seconds and nanos have different signs"
+    );
+
+    let message = ChronoTimeMessage {
+        created: chrono::Utc.with_ymd_and_hms(10_000, 1, 1, 0, 0, 0).unwrap(),
+        ..message
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_created().get_seconds(), 253_402_300_799);
+    assert!(ChronoTimeMessage::from_pb(pb_message).is_ok());
+}
+
+#[test]
+fn time_crate_fields() {
+    let message = TimeCrateMessage {
+        created: time::OffsetDateTime::from_unix_timestamp_nanos(-1_500_000_000).unwrap(),
+        timeout: time::Duration::new(-2, -100),
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_created().get_seconds(), -2);
+    assert_eq!(pb_message.get_created().get_nanos(), 500_000_000);
+    assert_eq!(TimeCrateMessage::from_pb(pb_message).unwrap(), message);
+
+    let message = TimeCrateMessage {
+        timeout: time::Duration::MIN,
+        ..message
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(pb_message.get_timeout().get_seconds(), -315_576_000_000);
+    assert_eq!(pb_message.get_timeout().get_nanos(), -999_999_999);
+    assert!(TimeCrateMessage::from_pb(pb_message).is_ok());
+}
+
+#[test]
+fn json_fields() {
+    let payload = serde_json::json!({
+        "name": "block",
+        "height": 42,
+        "offset": -7,
+        "confirmed": true,
+        "parents": [null, "abc", { "depth": 1.5 }],
+    });
+    let message = JsonMessage {
+        payload: payload.as_object().unwrap().clone(),
+        extra: serde_json::Value::Null,
+    };
+    let pb_message = message.to_pb();
+    assert_eq!(
+        pb_message.get_payload().get_fields()["height"].get_number_value(),
+        42.0
+    );
+    assert_eq!(JsonMessage::from_pb(pb_message).unwrap(), message);
+
+    // Numbers are stored as `double`, so integral floats are decoded as integers,
+    // and the integers which are not exactly representable by `f64` as floats.
+    let message = JsonMessage {
+        extra: serde_json::json!([42.0, -0.0, 9_007_199_254_740_993_u64, 0.5]),
+        ..message
+    };
+    let de_message = JsonMessage::from_pb(message.to_pb()).unwrap();
+    assert_eq!(
+        de_message.extra,
+        serde_json::json!([42, 0, 9_007_199_254_740_992.0, 0.5])
+    );
+
+    let mut pb_message = message.to_pb();
+    pb_message.mut_extra().set_number_value(f64::NAN);
+    let e = JsonMessage::from_pb(pb_message).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Non-finite number NaN cannot be represented in JSON"
+    );
+}
@@ -0,0 +1,82 @@
+// Copyright 2019 The Exonum Team, 2019 Witnet Foundation
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use prost_tests::{self as proto, ProtobufConvert};
+use protobuf_convert::ProtobufConvert;
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::User", backend = "prost")]
+struct User {
+    id: u32,
+    name: String,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::Account", backend = "prost")]
+struct Account {
+    owner: User,
+    #[protobuf_convert(enumeration)]
+    status: proto::Status,
+    key: Vec<u8>,
+    members: [User; 2],
+    #[protobuf_convert(wrapper)]
+    balance: Option<u64>,
+    referrer: Option<Box<User>>,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::Event", backend = "prost", oneof_field = "kind")]
+enum Event {
+    Created(User),
+    Updated(Account),
+}
+
+#[test]
+fn message_fields() {
+    let alice = User {
+        id: 1,
+        name: "alice".into(),
+    };
+    let account = Account {
+        owner: alice.clone(),
+        status: proto::Status::Active,
+        key: vec![1, 2, 3],
+        members: [alice.clone(), alice.clone()],
+        balance: Some(0),
+        referrer: None,
+    };
+    let pb_account = account.to_pb();
+    assert_eq!(pb_account.owner.as_ref().unwrap().name, "alice");
+    assert_eq!(pb_account.status, proto::Status::Active as i32);
+    assert_eq!(pb_account.balance, Some(0));
+    assert!(pb_account.referrer.is_none());
+    assert_eq!(Account::from_pb(pb_account).unwrap(), account);
+
+    let account = Account {
+        balance: None,
+        referrer: Some(Box::new(alice.clone())),
+        ..account
+    };
+    let event = Event::Updated(account);
+    assert_eq!(Event::from_pb(event.to_pb()).unwrap(), event);
+    let event = Event::Created(alice);
+    assert_eq!(Event::from_pb(event.to_pb()).unwrap(), event);
+
+    let pb_account = proto::Account {
+        status: 42,
+        ..Default::default()
+    };
+    let err = Account::from_pb(pb_account).unwrap_err();
+    assert_eq!(err.to_string(), "Unknown value 42 of enum field `status`");
+}
@@ -15,11 +15,43 @@
 pub use message::*;
 
 use anyhow::Error;
+use protobuf::{well_known_types::FieldMask, CodedInputStream, CodedOutputStream, ProtobufResult};
+use std::fmt;
 
+#[allow(
+    unknown_lints,
+    renamed_and_removed_lints,
+    unused_parens,
+    mismatched_lifetime_syntaxes
+)]
 pub mod message {
     include!(concat!(env!("OUT_DIR"), "/mod.rs"));
 }
 
+#[allow(
+    unknown_lints,
+    renamed_and_removed_lints,
+    unused_parens,
+    mismatched_lifetime_syntaxes
+)]
+pub mod fields {
+    include!(concat!(env!("OUT_DIR"), "/fields/mod.rs"));
+}
+
+/// Error returned for the field mask paths that do not select any field
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownMaskPath {
+    pub path: String,
+}
+
+impl fmt::Display for UnknownMaskPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown field mask path `{}`", self.path)
+    }
+}
+
+impl std::error::Error for UnknownMaskPath {}
+
 pub trait ProtobufConvert: Sized {
     /// Type of the protobuf clone of Self
     type ProtoStruct;
@@ -29,8 +61,78 @@ pub trait ProtobufConvert: Sized {
 
     /// ProtoStruct -> Struct
     fn from_pb(pb: Self::ProtoStruct) -> Result<Self, Error>;
+
+    /// Struct -> ProtoStruct, moving the fields of Struct
+    fn into_pb(self) -> Self::ProtoStruct {
+        self.to_pb()
+    }
+
+    /// Struct -> existing ProtoStruct, reusing its allocations
+    fn to_pb_into(&self, pb: &mut Self::ProtoStruct) {
+        *pb = self.to_pb();
+    }
+
+    /// ProtoStruct -> existing Struct, reusing its allocations
+    #[allow(clippy::wrong_self_convention)]
+    fn from_pb_into(&mut self, pb: Self::ProtoStruct) -> Result<(), Error> {
+        *self = Self::from_pb(pb)?;
+        Ok(())
+    }
+
+    /// Struct -> protobuf wire format, without the intermediate ProtoStruct
+    fn encode(&self, os: &mut CodedOutputStream<'_>) -> ProtobufResult<()>
+    where
+        Self::ProtoStruct: protobuf::Message,
+    {
+        protobuf::Message::write_to(&self.to_pb(), os)
+    }
+
+    /// Length of the protobuf wire format of Struct
+    fn encoded_len(&self) -> u32
+    where
+        Self::ProtoStruct: protobuf::Message,
+    {
+        protobuf::Message::compute_size(&self.to_pb())
+    }
+
+    /// Protobuf wire format -> Struct, without the intermediate ProtoStruct
+    fn decode(is: &mut CodedInputStream<'_>) -> Result<Self, Error>
+    where
+        Self::ProtoStruct: protobuf::Message,
+    {
+        Self::from_pb(protobuf::Message::parse_from(is)?)
+    }
+
+    /// Struct -> ProtoStruct with only the fields selected by the mask
+    fn to_pb_masked(&self, mask: &FieldMask) -> Result<Self::ProtoStruct, Error> {
+        match mask.get_paths().first() {
+            Some(path) => Err(UnknownMaskPath {
+                path: path.to_owned(),
+            }
+            .into()),
+            None => Ok(self.to_pb()),
+        }
+    }
+
+    /// Updates Struct with the fields set in ProtoStruct or selected by the mask
+    fn merge_from_pb(
+        &mut self,
+        pb: Self::ProtoStruct,
+        mask: Option<&FieldMask>,
+    ) -> Result<(), Error> {
+        if let Some(path) = mask.and_then(|mask| mask.get_paths().first()) {
+            return Err(UnknownMaskPath {
+                path: path.to_owned(),
+            }
+            .into());
+        }
+        *self = Self::from_pb(pb)?;
+        Ok(())
+    }
 }
 
+protobuf_convert::impl_well_known_types!(chrono, time, serde_json);
+
 impl ProtobufConvert for String {
     type ProtoStruct = Self;
 
@@ -41,13 +143,91 @@ impl ProtobufConvert for String {
     fn from_pb(pb: Self::ProtoStruct) -> Result<Self, Error> {
         Ok(pb)
     }
+
+    fn into_pb(self) -> Self::ProtoStruct {
+        self
+    }
+
+    fn to_pb_into(&self, pb: &mut Self::ProtoStruct) {
+        pb.clear();
+        pb.push_str(self);
+    }
+
+    fn from_pb_into(&mut self, pb: Self::ProtoStruct) -> Result<(), Error> {
+        self.clear();
+        self.push_str(&pb);
+        Ok(())
+    }
 }
 
 impl ProtobufConvert for u32 {
     type ProtoStruct = Self;
 
     fn to_pb(&self) -> Self::ProtoStruct {
-        u32::from(*self)
+        *self
+    }
+
+    fn from_pb(pb: Self::ProtoStruct) -> Result<Self, Error> {
+        Ok(pb)
+    }
+}
+
+impl ProtobufConvert for u64 {
+    type ProtoStruct = Self;
+
+    fn to_pb(&self) -> Self::ProtoStruct {
+        *self
+    }
+
+    fn from_pb(pb: Self::ProtoStruct) -> Result<Self, Error> {
+        Ok(pb)
+    }
+}
+
+impl ProtobufConvert for Vec<u8> {
+    type ProtoStruct = Self;
+
+    fn to_pb(&self) -> Self::ProtoStruct {
+        self.clone()
+    }
+
+    fn from_pb(pb: Self::ProtoStruct) -> Result<Self, Error> {
+        Ok(pb)
+    }
+
+    fn into_pb(self) -> Self::ProtoStruct {
+        self
+    }
+
+    fn to_pb_into(&self, pb: &mut Self::ProtoStruct) {
+        pb.clear();
+        pb.extend_from_slice(self);
+    }
+
+    fn from_pb_into(&mut self, pb: Self::ProtoStruct) -> Result<(), Error> {
+        self.clear();
+        self.extend_from_slice(&pb);
+        Ok(())
+    }
+}
+
+impl ProtobufConvert for Level {
+    type ProtoStruct = Self;
+
+    fn to_pb(&self) -> Self::ProtoStruct {
+        *self
+    }
+
+    fn from_pb(pb: Self::ProtoStruct) -> Result<Self, Error> {
+        Ok(pb)
+    }
+}
+
+impl ProtobufConvert for i32 {
+    type ProtoStruct = Self;
+
+    fn to_pb(&self) -> Self::ProtoStruct {
+        *self
     }
 
     fn from_pb(pb: Self::ProtoStruct) -> Result<Self, Error> {
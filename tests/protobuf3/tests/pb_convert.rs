@@ -0,0 +1,134 @@
+// Copyright 2019 The Exonum Team, 2019 Witnet Foundation
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use protobuf::{Message as _, UnknownFields};
+use protobuf3_tests::{self as proto, ProtobufConvert};
+use protobuf_convert::ProtobufConvert;
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::User", backend = "protobuf3", register)]
+struct User {
+    id: u32,
+    name: String,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::Account", backend = "protobuf3", register)]
+struct Account {
+    owner: User,
+    #[protobuf_convert(enumeration)]
+    status: proto::Status,
+    key: Vec<u8>,
+    #[protobuf_convert(wrapper)]
+    balance: Option<u64>,
+    referrer: Option<Box<User>>,
+    #[protobuf_convert(path = "meta.created", required)]
+    created: u64,
+    #[protobuf_convert(path = "meta.author")]
+    author: Option<Box<User>>,
+    #[protobuf_convert(unknown_fields)]
+    extra: UnknownFields,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::Event", backend = "protobuf3", oneof_field = "kind")]
+enum Event {
+    Created(User),
+    Updated(Account),
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(backend = "protobuf3", any)]
+enum Payload {
+    User(User),
+    Account(Account),
+}
+
+fn alice() -> User {
+    User {
+        id: 1,
+        name: "alice".into(),
+    }
+}
+
+fn account() -> Account {
+    Account {
+        owner: alice(),
+        status: proto::Status::STATUS_ACTIVE,
+        key: vec![1, 2, 3],
+        balance: Some(0),
+        referrer: None,
+        created: 1_600_000_000,
+        author: Some(Box::new(alice())),
+        extra: UnknownFields::new(),
+    }
+}
+
+#[test]
+fn message_fields() {
+    let account = account();
+    let pb_account = account.to_pb();
+    assert_eq!(pb_account.owner.name, "alice");
+    assert_eq!(pb_account.status.value(), 1);
+    assert_eq!(pb_account.balance.value, 0);
+    assert!(pb_account.balance.is_some());
+    assert!(pb_account.referrer.is_none());
+    assert_eq!(pb_account.meta.created, 1_600_000_000);
+    assert_eq!(Account::from_pb(pb_account).unwrap(), account);
+
+    let account = Account {
+        balance: None,
+        referrer: Some(Box::new(alice())),
+        author: None,
+        ..account
+    };
+    let pb_account = account.to_pb();
+    assert!(pb_account.balance.is_none());
+    assert_eq!(Account::from_pb(pb_account).unwrap(), account);
+
+    let mut pb_account = account.to_pb();
+    pb_account.status = protobuf::EnumOrUnknown::from_i32(42);
+    let err = Account::from_pb(pb_account).unwrap_err();
+    assert_eq!(err.to_string(), "Unknown value 42 of enum field `status`");
+
+    let mut pb_account = account.to_pb();
+    pb_account.meta.clear();
+    let err = Account::from_pb(pb_account).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Missing message `meta` required by field `created`"
+    );
+}
+
+#[test]
+fn unknown_fields() {
+    let mut pb_account = account().to_pb();
+    pb_account.mut_unknown_fields().add_varint(100, 42);
+    let bytes = pb_account.write_to_bytes().unwrap();
+
+    let account = Account::from_pb(proto::Account::parse_from_bytes(&bytes).unwrap()).unwrap();
+    assert_eq!(account.to_pb().write_to_bytes().unwrap(), bytes);
+}
+
+#[test]
+fn oneof_and_any() {
+    for event in [Event::Created(alice()), Event::Updated(account())] {
+        assert_eq!(Event::from_pb(event.to_pb()).unwrap(), event);
+    }
+
+    let payload = Payload::Account(account());
+    let pb_payload = payload.to_pb();
+    assert_eq!(pb_payload.type_url, "type.googleapis.com/protobuf3.Account");
+    assert_eq!(Payload::from_pb(pb_payload).unwrap(), payload);
+}
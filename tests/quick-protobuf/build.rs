@@ -0,0 +1,36 @@
+// Copyright 2019 The Exonum Team, 2019 Witnet Foundation
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use pb_rs::{types::FileDescriptor, ConfigBuilder};
+
+use std::{fs, path::Path};
+
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").expect("Unable to get OUT_DIR");
+    let out_dir = Path::new(&out_dir);
+
+    let config = ConfigBuilder::new(
+        &[Path::new("proto/message.proto")],
+        None,
+        Some(&out_dir),
+        &[Path::new("proto")],
+    )
+    .expect("Couldn't configure pb-rs");
+    FileDescriptor::run(&config.build()).expect("Couldn't compile quick-protobuf sources");
+    fs::write(
+        out_dir.join("mod.rs"),
+        "pub use self::message::*; mod message;",
+    )
+    .expect("Unable to write mod.rs file");
+}
@@ -0,0 +1,89 @@
+// Copyright 2019 The Exonum Team, 2019 Witnet Foundation
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use protobuf_convert::ProtobufConvert;
+use quick_protobuf_tests::{self as proto, ProtobufConvert};
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::Header<'static>", backend = "quick-protobuf")]
+struct Header {
+    height: u64,
+    hash: Vec<u8>,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::Block<'static>", backend = "quick-protobuf")]
+struct Block {
+    header: Header,
+    miner: String,
+    status: proto::Status,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::Header<'a>", backend = "quick-protobuf")]
+struct HeaderRef<'a> {
+    height: u64,
+    hash: Cow<'a, [u8]>,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(source = "proto::Block<'a>", backend = "quick-protobuf")]
+struct BlockRef<'a> {
+    header: HeaderRef<'a>,
+    miner: Cow<'a, str>,
+    status: proto::Status,
+}
+
+#[derive(Debug, Clone, ProtobufConvert, Eq, PartialEq)]
+#[protobuf_convert(
+    source = "proto::Event<'static>",
+    backend = "quick-protobuf",
+    oneof_field = "kind",
+    rename(case = "snake_case")
+)]
+enum Event {
+    Header(Header),
+    Block(Block),
+}
+
+#[test]
+fn message_fields() {
+    let block = Block {
+        header: Header {
+            height: 42,
+            hash: vec![0xab; 4],
+        },
+        miner: "alice".into(),
+        status: proto::Status::FINAL,
+    };
+    assert_eq!(Block::from_pb(block.to_pb()).unwrap(), block);
+
+    // Borrowed messages are converted without copying the strings and bytes.
+    let bytes = quick_protobuf::serialize_into_vec(&block.to_pb()).unwrap();
+    let pb_block: proto::Block = quick_protobuf::deserialize_from_slice(&bytes).unwrap();
+    let block_ref = BlockRef::from_pb(pb_block).unwrap();
+    assert_eq!(block_ref.header.height, 42);
+    assert!(matches!(
+        block_ref.header.hash,
+        Cow::Borrowed([0xab, 0xab, 0xab, 0xab])
+    ));
+    assert!(matches!(block_ref.miner, Cow::Borrowed("alice")));
+    assert_eq!(block_ref.status, proto::Status::FINAL);
+
+    let event = Event::Block(block);
+    assert_eq!(Event::from_pb(event.to_pb()).unwrap(), event);
+    let err = Event::from_pb(proto::Event::default()).unwrap_err();
+    assert!(err.to_string().starts_with("Failed to decode"));
+}